@@ -1,14 +1,22 @@
 use xzenfmt_core::{
-    CliArgs, Command as CoreCommand, OperationMode, ProcessedFileResult, XzenfmtArgs,
-    check_dependencies, find_files, process_files,
+    BackupRun, CacheAction, CacheArgs, CliArgs, Command as CoreCommand, CommentFilter,
+    CommentsArgs, Config, ConfigAction, ConfigArgs, HistoryArgs, HookAction, HookArgs,
+    LanguagesArgs, LineSelection, OperationMode, OutputFormat, ProcessError, ProcessOptions,
+    ProcessedFileResult, ResultCache, RunSummary, StatsArgs, UndoArgs, XzenfmtArgs, changed_lines,
+    check_dependencies, clear_cache, collect_comments, collect_stats, describe_languages,
+    find_files, format_timestamp, install_hook, line_ranges, list_runs, load_config,
+    process_content, process_files, render_comments, render_config, render_languages,
+    render_report, render_stats, state_root, undo_run, uninstall_hook,
 };
 mod interaction;
 
 use anyhow::Result;
 use clap::{CommandFactory, Parser};
 use console::style;
-use std::io;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process::ExitCode;
+use std::sync::Arc;
 
 fn print_completions_cli(shell: clap_complete::Shell) {
     let mut cmd = CliArgs::command();
@@ -16,19 +24,402 @@ fn print_completions_cli(shell: clap_complete::Shell) {
     clap_complete::generate(shell, &mut cmd, name, &mut io::stdout());
 }
 
-fn determine_operation_mode(args: &XzenfmtArgs) -> OperationMode {
+fn error_exit_code(error: &ProcessError) -> ExitCode {
+    ExitCode::from(match error {
+        ProcessError::FormatterMissing { .. } => 3,
+        ProcessError::FormatterFailed { .. } => 4,
+        ProcessError::TimedOut { .. } => 5,
+        ProcessError::NotUtf8 { .. } | ProcessError::InvalidOutput { .. } => 6,
+        ProcessError::Strip { .. } => 7,
+        ProcessError::PermissionDenied { .. }
+        | ProcessError::Io { .. }
+        | ProcessError::Backup { .. } => 8,
+        ProcessError::Config { .. } => 9,
+        ProcessError::NotIdempotent { .. } => 10,
+        ProcessError::RangesUnsupported { .. } => 11,
+    })
+}
+
+fn load_config_or_report(target: &Path) -> Option<Config> {
+    match load_config(target) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            eprintln!("{}", style(format!("Config Error: {:#}", e)).red());
+            None
+        }
+    }
+}
+
+fn run_comments_command(args: &CommentsArgs) -> Result<ExitCode> {
+    let grep = match args.grep.as_deref().map(regex::Regex::new).transpose() {
+        Ok(grep) => grep,
+        Err(e) => {
+            eprintln!("{}", style(format!("Invalid --grep pattern: {}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let filter = CommentFilter {
+        grep,
+        kinds: args.kind.clone(),
+        tags: args.tag.clone(),
+    };
+    let Some(config) = load_config_or_report(&args.selection.path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let files = match find_files(&args.selection.to_xzenfmt_args(), &config) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", style(format!("Error finding files: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    match collect_comments(&files, &filter, &config)
+        .and_then(|records| render_comments(&records, args.format, &args.selection.path))
+    {
+        Ok(output) => {
+            print!("{}", output);
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style(format!("Error collecting comments: {}", e)).red()
+            );
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn run_stats_command(args: &StatsArgs) -> Result<ExitCode> {
+    let Some(config) = load_config_or_report(&args.selection.path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let files = match find_files(&args.selection.to_xzenfmt_args(), &config) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", style(format!("Error finding files: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let report = match collect_stats(&files, &args.selection.path, &config) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", style(format!("Error collecting stats: {}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    match render_stats(&report, args.format) {
+        Ok(output) => print!("{}", output),
+        Err(e) => {
+            eprintln!("{}", style(format!("Error rendering stats: {}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    if let Some(threshold) = args.threshold {
+        let offenders = report.files_over_threshold(threshold);
+        if !offenders.is_empty() {
+            for file in &offenders {
+                eprintln!(
+                    "  {} {} comment ratio {:.2} exceeds {:.2}",
+                    style("⚠️").yellow(),
+                    style(file.path.display()).dim(),
+                    file.stats.comment_ratio,
+                    threshold
+                );
+            }
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_config_command(args: &ConfigArgs) -> Result<ExitCode> {
+    match &args.action {
+        ConfigAction::Show(show) => {
+            let Some(config) = load_config_or_report(&show.path) else {
+                return Ok(ExitCode::FAILURE);
+            };
+            print!("{}", render_config(&config));
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn run_undo_command(args: &UndoArgs) -> Result<ExitCode> {
+    let root = state_root(&args.path);
+    let report = match undo_run(&root, args.run_id.as_deref(), args.force) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", style(format!("Undo Error: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    if report.restored.is_empty() && !report.conflicts.is_empty() {
+        eprintln!(
+            "{}",
+            style(format!(
+                "Run {} not undone: files changed since the run (use --force to overwrite):",
+                report.run_id
+            ))
+            .red()
+        );
+        for path in &report.conflicts {
+            eprintln!("  {}", style(path.display()).dim());
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+    for path in &report.restored {
+        println!("  restored {}", style(path.display()).dim());
+    }
+    println!(
+        "Undid run {}: {} {} restored.",
+        style(&report.run_id).cyan(),
+        style(report.restored.len()).green(),
+        if report.restored.len() == 1 {
+            "file"
+        } else {
+            "files"
+        }
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_history_command(args: &HistoryArgs) -> Result<ExitCode> {
+    let root = state_root(&args.path);
+    let runs = match list_runs(&root) {
+        Ok(runs) => runs,
+        Err(e) => {
+            eprintln!("{}", style(format!("History Error: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    if runs.is_empty() {
+        println!("No backed-up runs in {}.", root.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+    for run in runs.iter().rev() {
+        println!(
+            "{}  {}  {:<16} {:>4} {}{}",
+            style(&run.run_id).cyan(),
+            format_timestamp(run.timestamp),
+            format!("{:?}", run.mode),
+            run.files.len(),
+            if run.files.len() == 1 {
+                "file"
+            } else {
+                "files"
+            },
+            if run.undone {
+                "  (undone)"
+            } else if run.incomplete {
+                "  (incomplete)"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_cache_command(args: &CacheArgs) -> Result<ExitCode> {
+    match &args.action {
+        CacheAction::Clear(clear) => {
+            let root = state_root(&clear.path);
+            match clear_cache(&root) {
+                Ok(true) => println!("Cleared cache in {}.", root.display()),
+                Ok(false) => println!("No cache in {}.", root.display()),
+                Err(e) => {
+                    eprintln!("{}", style(format!("Cache Error: {:#}", e)).red());
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn run_languages_command(args: &LanguagesArgs) -> Result<ExitCode> {
+    let Some(config) = load_config_or_report(&args.path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    match render_languages(&describe_languages(&config), args.format) {
+        Ok(output) => print!("{}", output),
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style(format!("Error rendering languages: {:#}", e)).red()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_hook_command(args: &HookArgs) -> Result<ExitCode> {
+    let outcome = match &args.action {
+        HookAction::Install(install) => {
+            let hook_args = ["xzenfmt", "--no-confirm", "--index"]
+                .into_iter()
+                .map(str::to_string)
+                .chain(install.args.iter().cloned());
+            if let Err(e) = XzenfmtArgs::try_parse_from(hook_args) {
+                eprintln!(
+                    "{}",
+                    style(format!(
+                        "Invalid hook arguments: {}",
+                        e.to_string().lines().next().unwrap_or_default()
+                    ))
+                    .red()
+                );
+                return Ok(ExitCode::FAILURE);
+            }
+            std::env::current_exe()
+                .map_err(anyhow::Error::from)
+                .and_then(|exe| install_hook(&install.path, &exe, &install.args, install.force))
+                .map(|path| format!("Installed pre-commit hook at {}.", path.display()))
+        }
+        HookAction::Uninstall(uninstall) => {
+            uninstall_hook(&uninstall.path).map(|removed| match removed {
+                Some(path) => format!("Removed pre-commit hook at {}.", path.display()),
+                None => "No pre-commit hook installed.".to_string(),
+            })
+        }
+    };
+    match outcome {
+        Ok(message) => {
+            println!("{}", message);
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!("{}", style(format!("Hook Error: {:#}", e)).red());
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn run_stdin_mode(args: &XzenfmtArgs) -> Result<ExitCode> {
+    let Some(stdin_filepath) = args.stdin_filepath.as_deref() else {
+        eprintln!("{}", style("--stdin requires --stdin-filepath").red());
+        return Ok(ExitCode::FAILURE);
+    };
+    let Some(config) = load_config_or_report(stdin_filepath) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let mut input = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut input) {
+        eprintln!("{}", style(format!("Error reading stdin: {}", e)).red());
+        return Ok(ExitCode::FAILURE);
+    }
+    let mode = determine_operation_mode(args, &config);
+    let mut options = determine_process_options(args, config);
+    match line_selection(args, stdin_filepath) {
+        Ok(lines) => options.lines = lines,
+        Err(e) => {
+            eprintln!("{}", style(format!("Git Error: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    match process_content(&input, stdin_filepath, mode, &options) {
+        Ok(output) => {
+            io::stdout().write_all(output.as_bytes())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!("{}", style(format!("Error processing stdin: {}", e)).red());
+            Ok(error_exit_code(&e))
+        }
+    }
+}
+
+fn line_selection(args: &XzenfmtArgs, target: &Path) -> Result<Option<Arc<LineSelection>>> {
+    if !args.lines.is_empty() {
+        let ranges = line_ranges::normalize(args.lines.clone());
+        return Ok(Some(Arc::new(LineSelection::All(ranges))));
+    }
+    if args.git.changed_lines {
+        let files = changed_lines(target, &args.git)?;
+        return Ok(Some(Arc::new(LineSelection::PerFile(files))));
+    }
+    Ok(None)
+}
+
+fn print_text_report(results: &[ProcessedFileResult], summary: &RunSummary) {
+    println!("\nProcessing complete.");
+    for result in results {
+        if let Some(err_msg) = &result.error {
+            let stage = result
+                .metrics
+                .failed_stage
+                .map(|stage| format!(" ({:?} stage)", stage).to_lowercase())
+                .unwrap_or_default();
+            eprintln!(
+                "  {} Failed{}: {} - {}",
+                style("⚠️").yellow(),
+                stage,
+                style(result.path.display()).dim(),
+                style(err_msg).red()
+            );
+        }
+    }
+    let success_count = summary.files - summary.failed - summary.timed_out;
+    let failure_count = summary.failed + summary.timed_out;
+    println!(
+        "Result: {} {} processed successfully, {} {} failed.",
+        style(success_count).green(),
+        if success_count == 1 { "file" } else { "files" },
+        style(failure_count).red(),
+        if failure_count == 1 { "file" } else { "files" }
+    );
+    if summary.cached > 0 {
+        println!(
+            "{} {} already clean according to the cache.",
+            style(summary.cached).dim(),
+            if summary.cached == 1 {
+                "file was"
+            } else {
+                "files were"
+            }
+        );
+    }
+}
+
+fn cli_operation_mode(args: &XzenfmtArgs) -> Option<OperationMode> {
     if args.all {
-        OperationMode::All
-    } else if args.strip_comments {
-        OperationMode::Strip
+        Some(OperationMode::All)
+    } else if args.strip_comments || args.comments.is_some() {
+        Some(OperationMode::Strip)
     } else if args.strip_whitespace {
-        OperationMode::StripWhitespace
+        Some(OperationMode::StripWhitespace)
     } else if args.strip_newlines {
-        OperationMode::StripNewlines
+        Some(OperationMode::StripNewlines)
+    } else if args.strip_dead_code_comments {
+        Some(OperationMode::StripDeadCode)
+    } else if args.strip_redundant {
+        Some(OperationMode::StripRedundant)
     } else if args.code_format {
-        OperationMode::Format
+        Some(OperationMode::Format)
     } else {
-        OperationMode::Format
+        None
+    }
+}
+
+fn determine_operation_mode(args: &XzenfmtArgs, config: &Config) -> OperationMode {
+    cli_operation_mode(args)
+        .or(config.mode)
+        .unwrap_or(OperationMode::Format)
+}
+
+fn determine_process_options(args: &XzenfmtArgs, config: Config) -> ProcessOptions {
+    ProcessOptions {
+        comments: args.comments,
+        explain: args.explain,
+        config,
+        backup: None,
+        cache: None,
+        skip_strip_validation: args.no_strip_validation,
+        verify: args.verify,
+        lines: None,
+        index: args.index,
     }
 }
 
@@ -43,13 +434,27 @@ async fn main() -> Result<ExitCode> {
                 print_completions_cli(args.shell);
                 return Ok(ExitCode::SUCCESS);
             }
+            CoreCommand::Comments(args) => return run_comments_command(&args),
+            CoreCommand::Stats(args) => return run_stats_command(&args),
+            CoreCommand::Config(args) => return run_config_command(&args),
+            CoreCommand::Undo(args) => return run_undo_command(&args),
+            CoreCommand::History(args) => return run_history_command(&args),
+            CoreCommand::Cache(args) => return run_cache_command(&args),
+            CoreCommand::Hook(args) => return run_hook_command(&args),
+            CoreCommand::Languages(args) => return run_languages_command(&args),
         }
     }
 
-    let main_app_args = cli.main_opts;
+    let mut main_app_args = cli.main_opts;
+    if main_app_args.index {
+        main_app_args.git.staged = true;
+    }
 
     if main_app_args.check_dependencies {
-        match check_dependencies(&main_app_args.lang) {
+        let Some(config) = load_config_or_report(&main_app_args.path) else {
+            return Ok(ExitCode::FAILURE);
+        };
+        match check_dependencies(&main_app_args.lang, &config) {
             Ok(_) => return Ok(ExitCode::SUCCESS),
             Err(e) => {
                 eprintln!("{}", style(format!("Dependency Check Error: {}", e)).red());
@@ -58,28 +463,40 @@ async fn main() -> Result<ExitCode> {
         }
     }
 
-    let files_to_process = match find_files(&main_app_args) {
+    if main_app_args.stdin {
+        return run_stdin_mode(&main_app_args);
+    }
+
+    let Some(config) = load_config_or_report(&main_app_args.path) else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    let files_to_process = match find_files(&main_app_args, &config) {
         Ok(files) => files,
         Err(e) => {
-            eprintln!("{}", style(format!("Error finding files: {}", e)).red());
+            eprintln!("{}", style(format!("Error finding files: {:#}", e)).red());
             return Ok(ExitCode::FAILURE);
         }
     };
 
-    if files_to_process.is_empty() {
+    let text_output = main_app_args.output_format == OutputFormat::Text;
+    if files_to_process.is_empty() && text_output {
         println!("No files found matching the criteria.");
         return Ok(ExitCode::SUCCESS);
     }
 
-    println!("Found {} files:", files_to_process.len());
-    for file in files_to_process.iter().take(10) {
-        println!("  {}", style(file.display()).dim());
-    }
-    if files_to_process.len() > 10 {
-        println!("  ... and {} more.", files_to_process.len() - 10);
+    if text_output {
+        println!("Found {} files:", files_to_process.len());
+        for file in files_to_process.iter().take(10) {
+            println!("  {}", style(file.display()).dim());
+        }
+        if files_to_process.len() > 10 {
+            println!("  ... and {} more.", files_to_process.len() - 10);
+        }
     }
 
-    match interaction::confirm_processing(files_to_process.len(), main_app_args.no_confirm) {
+    let no_confirm = main_app_args.no_confirm || files_to_process.is_empty();
+    match interaction::confirm_processing(files_to_process.len(), no_confirm) {
         Ok(true) => {}
         Ok(false) => return Ok(ExitCode::SUCCESS),
         Err(e) => {
@@ -91,49 +508,104 @@ async fn main() -> Result<ExitCode> {
         }
     }
 
-    let operation_mode = determine_operation_mode(&main_app_args);
-    println!("Processing files (Mode: {:?})...", operation_mode);
-
-    let processing_results: Vec<ProcessedFileResult> =
-        match process_files(files_to_process, operation_mode) {
-            Ok(results) => results,
+    let operation_mode = determine_operation_mode(&main_app_args, &config);
+    let backup_enabled = main_app_args.backup || config.backup.enabled.unwrap_or(false);
+    let cache_enabled = !main_app_args.no_cache && config.cache.enabled.unwrap_or(true);
+    let mut process_options = determine_process_options(&main_app_args, config);
+    match line_selection(&main_app_args, &main_app_args.path) {
+        Ok(lines) => process_options.lines = lines,
+        Err(e) => {
+            eprintln!("{}", style(format!("Git Error: {:#}", e)).red());
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    let state_dir = state_root(&main_app_args.path);
+    if cache_enabled {
+        process_options.cache = ResultCache::open(&state_dir).map(Arc::new);
+    }
+    if backup_enabled {
+        match BackupRun::start(&state_dir, operation_mode) {
+            Ok(run) => process_options.backup = Some(Arc::new(run)),
             Err(e) => {
-                eprintln!(
-                    "{}",
-                    style(format!("Critical error during processing setup: {}", e)).red()
-                );
+                eprintln!("{}", style(format!("Backup Error: {:#}", e)).red());
                 return Ok(ExitCode::FAILURE);
             }
-        };
+        }
+    }
+    if text_output {
+        println!("Processing files (Mode: {:?})...", operation_mode);
+    }
 
-    let mut success_count = 0;
-    let mut failure_count = 0;
-    println!("\nProcessing complete.");
-    for result in processing_results {
-        match result.error {
-            None => {
-                success_count += 1;
+    let processing_results: Vec<ProcessedFileResult> = match process_files(
+        files_to_process,
+        cli_operation_mode(&main_app_args),
+        &process_options,
+    ) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style(format!("Critical error during processing setup: {}", e)).red()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    if let Some(cache) = process_options.cache.take().and_then(Arc::into_inner)
+        && let Err(e) = cache.save()
+    {
+        eprintln!(
+            "  {} {}",
+            style("⚠️").yellow(),
+            style(format!("Could not save cache: {:#}", e)).dim()
+        );
+    }
+
+    let summary = RunSummary::from_results(&processing_results);
+    if text_output {
+        print_text_report(&processing_results, &summary);
+    } else {
+        match render_report(
+            &processing_results,
+            operation_mode,
+            main_app_args.output_format,
+        ) {
+            Ok(report) => print!("{}", report),
+            Err(e) => {
+                eprintln!("{}", style(format!("Report Error: {:#}", e)).red());
+                exit_code = ExitCode::FAILURE;
             }
-            Some(err_msg) => {
-                eprintln!(
-                    "  {} Failed: {} - {}",
-                    style("⚠️").yellow(),
-                    style(result.path.display()).dim(),
-                    style(err_msg).red()
+        }
+    }
+    if let Some(run) = process_options.backup.take().and_then(Arc::into_inner) {
+        match run.finish() {
+            Ok(Some(manifest)) => {
+                let message = format!(
+                    "Backed up {} {} as run {} (undo with `xzenfmt undo`).",
+                    manifest.files.len(),
+                    if manifest.files.len() == 1 {
+                        "file"
+                    } else {
+                        "files"
+                    },
+                    style(&manifest.run_id).cyan()
                 );
-                failure_count += 1;
+                if text_output {
+                    println!("{}", message);
+                } else {
+                    eprintln!("{}", message);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("{}", style(format!("Backup Error: {:#}", e)).red());
+                exit_code = ExitCode::FAILURE;
             }
         }
     }
-    println!(
-        "Result: {} {} processed successfully, {} {} failed.",
-        style(success_count).green(),
-        if success_count == 1 { "file" } else { "files" },
-        style(failure_count).red(),
-        if failure_count == 1 { "file" } else { "files" }
-    );
-    if failure_count > 0 {
-        exit_code = ExitCode::FAILURE;
+
+    if let Some(error) = processing_results.iter().find_map(|r| r.error.as_ref()) {
+        exit_code = error_exit_code(error);
     }
 
     Ok(exit_code)
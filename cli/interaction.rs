@@ -19,7 +19,7 @@ pub fn confirm_processing(file_count: usize, no_confirm: bool) -> Result<bool> {
         .interact()?;
 
     if !confirmed {
-        println!("Aborted by user.");
+        eprintln!("Aborted by user.");
     }
 
     Ok(confirmed)
@@ -0,0 +1,250 @@
+use crate::cache::content_hash;
+use crate::config::CONFIG_FILE_NAME;
+use crate::processor::OperationMode;
+use crate::safe_write::write_atomically;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const STATE_DIR_NAME: &str = ".xzenfmt";
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub backup: String,
+    pub written_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub run_id: String,
+    pub timestamp: u64,
+    pub mode: OperationMode,
+    pub files: Vec<BackupEntry>,
+    #[serde(default)]
+    pub undone: bool,
+    #[serde(default)]
+    pub incomplete: bool,
+}
+
+#[derive(Debug)]
+pub struct BackupRun {
+    dir: PathBuf,
+    manifest: Mutex<BackupManifest>,
+}
+
+#[derive(Debug)]
+pub struct UndoReport {
+    pub run_id: String,
+    pub restored: Vec<PathBuf>,
+    pub conflicts: Vec<PathBuf>,
+}
+
+pub fn state_root(target: &Path) -> PathBuf {
+    let absolute = std::path::absolute(target).unwrap_or_else(|_| target.to_path_buf());
+    let start = if absolute.is_dir() {
+        absolute.as_path()
+    } else {
+        absolute.parent().unwrap_or(&absolute)
+    };
+    start
+        .ancestors()
+        .find(|dir| dir.join(STATE_DIR_NAME).is_dir() || dir.join(CONFIG_FILE_NAME).is_file())
+        .unwrap_or(start)
+        .to_path_buf()
+}
+
+fn backups_dir(root: &Path) -> PathBuf {
+    root.join(STATE_DIR_NAME).join("backups")
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn new_run_id(secs: u64, nanos: u32) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        nanos / 1_000_000
+    )
+}
+
+impl BackupRun {
+    pub fn start(root: &Path, mode: OperationMode) -> Result<Self> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let run_id = new_run_id(now.as_secs(), now.subsec_nanos());
+        let dir = backups_dir(root).join(&run_id);
+        fs::create_dir_all(dir.join("files"))
+            .with_context(|| format!("Failed to create backup dir {}", dir.display()))?;
+        Ok(BackupRun {
+            dir,
+            manifest: Mutex::new(BackupManifest {
+                run_id,
+                timestamp: now.as_secs(),
+                mode,
+                files: Vec::new(),
+                undone: false,
+                incomplete: true,
+            }),
+        })
+    }
+
+    pub fn run_id(&self) -> String {
+        self.manifest.lock().unwrap().run_id.clone()
+    }
+
+    /// Backs up `original` and updates the manifest on disk, so it must be called
+    /// before the file is replaced. Recording a path again only updates the
+    /// expected content and keeps the first backup.
+    pub fn record(&self, path: &Path, original: &str, written: &str) -> Result<()> {
+        let absolute = fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve {}", path.display()))?;
+        let written_hash = content_hash(written.as_bytes());
+        let mut manifest = self.manifest.lock().unwrap();
+        if let Some(entry) = manifest.files.iter_mut().find(|e| e.path == absolute) {
+            entry.written_hash = written_hash;
+        } else {
+            let backup = format!("files/{}", manifest.files.len());
+            fs::write(self.dir.join(&backup), original)
+                .with_context(|| format!("Failed to back up {}", path.display()))?;
+            manifest.files.push(BackupEntry {
+                path: absolute,
+                backup,
+                written_hash,
+            });
+        }
+        write_manifest(&self.dir, &manifest)
+    }
+
+    pub fn finish(self) -> Result<Option<BackupManifest>> {
+        let mut manifest = self.manifest.into_inner().unwrap();
+        if manifest.files.is_empty() {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove {}", self.dir.display()))?;
+            return Ok(None);
+        }
+        manifest.incomplete = false;
+        write_manifest(&self.dir, &manifest)?;
+        Ok(Some(manifest))
+    }
+}
+
+fn write_manifest(dir: &Path, manifest: &BackupManifest) -> Result<()> {
+    let json = serde_json::to_string_pretty(manifest).context("Serialize backup manifest")?;
+    let mut temp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create temp file in {}", dir.display()))?;
+    temp.write_all(json.as_bytes())
+        .and_then(|()| temp.as_file().sync_all())
+        .context("Failed to write manifest")?;
+    temp.persist(dir.join(MANIFEST_NAME))
+        .with_context(|| format!("Failed to write manifest in {}", dir.display()))?;
+    Ok(())
+}
+
+pub fn list_runs(root: &Path) -> Result<Vec<BackupManifest>> {
+    let dir = backups_dir(root);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let manifest_path = entry?.path().join(MANIFEST_NAME);
+        if !manifest_path.is_file() {
+            continue;
+        }
+        let json = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+        let manifest: BackupManifest = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid manifest {}", manifest_path.display()))?;
+        runs.push(manifest);
+    }
+    runs.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+    Ok(runs)
+}
+
+pub fn undo_run(root: &Path, run_id: Option<&str>, force: bool) -> Result<UndoReport> {
+    let runs = list_runs(root)?;
+    let mut manifest = match run_id {
+        Some(id) => runs
+            .into_iter()
+            .find(|run| run.run_id == id)
+            .with_context(|| format!("No backup run '{}' in {}", id, root.display()))?,
+        None => runs
+            .into_iter()
+            .rev()
+            .find(|run| !run.undone)
+            .with_context(|| format!("No run to undo in {}", root.display()))?,
+    };
+    let dir = backups_dir(root).join(&manifest.run_id);
+    let mut pending = Vec::new();
+    let mut conflicts = Vec::new();
+    for entry in &manifest.files {
+        let original = fs::read_to_string(dir.join(&entry.backup))
+            .with_context(|| format!("Failed to read backup of {}", entry.path.display()))?;
+        let current = fs::read(&entry.path).unwrap_or_default();
+        if current == original.as_bytes() {
+            // An interrupted run may have backed the file up without replacing it.
+            continue;
+        }
+        if content_hash(&current) != entry.written_hash {
+            conflicts.push(entry.path.clone());
+        }
+        pending.push((entry, original));
+    }
+    let mut report = UndoReport {
+        run_id: manifest.run_id.clone(),
+        restored: Vec::new(),
+        conflicts,
+    };
+    if !force && !report.conflicts.is_empty() {
+        return Ok(report);
+    }
+    for (entry, original) in pending {
+        if entry.path.exists() {
+            write_atomically(&entry.path, &original, false)
+        } else {
+            fs::write(&entry.path, &original).map_err(Into::into)
+        }
+        .with_context(|| format!("Failed to restore {}", entry.path.display()))?;
+        report.restored.push(entry.path.clone());
+    }
+    manifest.undone = true;
+    write_manifest(&dir, &manifest)?;
+    Ok(report)
+}
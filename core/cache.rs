@@ -0,0 +1,212 @@
+use crate::config::Config;
+use crate::formatters::FormatterRegistry;
+use crate::processor::{CommentStripMode, OperationMode, ProcessOptions};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const CACHE_FILE_NAME: &str = "clean.json";
+const MAX_ENTRIES: usize = 200_000;
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn cache_home() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(cache_home.join("xzenfmt"))
+}
+
+fn cache_dir(root: &Path) -> Option<PathBuf> {
+    let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+    let id = content_hash(root.to_string_lossy().as_bytes());
+    Some(cache_home()?.join(id))
+}
+
+fn config_fingerprint(config: &Config) -> String {
+    let effective = Config {
+        sources: Vec::new(),
+        origins: Default::default(),
+        scope: None,
+        ..config.clone()
+    };
+    format!("{:?}", effective)
+}
+
+fn uses_formatter(mode: OperationMode) -> bool {
+    matches!(mode, OperationMode::Format | OperationMode::All)
+}
+
+fn cache_key(
+    content: &str,
+    lang: &str,
+    mode: OperationMode,
+    comments: Option<CommentStripMode>,
+    formatter: &str,
+    config: &Config,
+) -> String {
+    let parts = format!(
+        "{}\0{}\0{:?}\0{:?}\0{}\0{}\0{}",
+        content_hash(content.as_bytes()),
+        lang,
+        mode,
+        comments,
+        env!("CARGO_PKG_VERSION"),
+        formatter,
+        config_fingerprint(config)
+    );
+    content_hash(parts.as_bytes())
+}
+
+#[derive(Debug)]
+pub struct ResultCache {
+    file: PathBuf,
+    known: HashSet<String>,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl ResultCache {
+    pub fn open(root: &Path) -> Option<Self> {
+        cache_dir(root).map(|dir| Self::load(dir.join(CACHE_FILE_NAME)))
+    }
+
+    fn load(file: PathBuf) -> Self {
+        let known = fs::read_to_string(&file)
+            .ok()
+            .and_then(|json| serde_json::from_str::<HashSet<String>>(&json).ok())
+            .unwrap_or_default();
+        ResultCache {
+            file,
+            known,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn key(content: &str, lang: &str, mode: OperationMode, options: &ProcessOptions) -> String {
+        let formatter = if uses_formatter(mode) {
+            FormatterRegistry::new(&options.config).fingerprint(lang)
+        } else {
+            String::new()
+        };
+        cache_key(
+            content,
+            lang,
+            mode,
+            options.comments,
+            &formatter,
+            &options.config,
+        )
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        let hit = self.known.contains(key);
+        if hit {
+            self.seen.lock().unwrap().insert(key.to_string());
+        }
+        hit
+    }
+
+    pub fn insert(&self, key: String) {
+        self.seen.lock().unwrap().insert(key);
+    }
+
+    pub fn save(self) -> Result<()> {
+        let seen = self.seen.into_inner().unwrap();
+        let mut entries = self.known;
+        if entries.len() + seen.len() > MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.extend(seen);
+        let dir = self.file.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create cache dir {}", dir.display()))?;
+        let json = serde_json::to_string(&entries).context("Serialize cache")?;
+        let mut temp = tempfile::NamedTempFile::new_in(dir).context("Create cache temp file")?;
+        temp.write_all(json.as_bytes())
+            .context("Write cache temp file")?;
+        temp.persist(&self.file)
+            .with_context(|| format!("Failed to write {}", self.file.display()))?;
+        Ok(())
+    }
+}
+
+pub fn clear_cache(root: &Path) -> Result<bool> {
+    let Some(dir) = cache_dir(root).filter(|dir| dir.exists()) else {
+        return Ok(false);
+    };
+    fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::process_files;
+
+    fn counting_config(marker: &Path) -> Config {
+        toml::from_str(&format!(
+            "[languages.rust]\nformatter = \"count\"\n[formatters.count]\ncommand = \"sh\"\nargs = [\"-c\", \"echo run >> '{}'\", \"sh\"]\n",
+            marker.display()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn hit_skips_the_formatter() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.rs");
+        let marker = dir.path().join("runs");
+        fs::write(&file, "fn main() {}\n").unwrap();
+        let cache_file = dir.path().join("cache").join(CACHE_FILE_NAME);
+        let run = || {
+            let options = ProcessOptions {
+                config: counting_config(&marker),
+                cache: Some(std::sync::Arc::new(ResultCache::load(cache_file.clone()))),
+                ..ProcessOptions::default()
+            };
+            let results =
+                process_files(vec![file.clone()], Some(OperationMode::Format), &options).unwrap();
+            std::sync::Arc::into_inner(options.cache.unwrap())
+                .unwrap()
+                .save()
+                .unwrap();
+            results[0].cached
+        };
+        assert!(!run());
+        assert!(run());
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn key_changes_with_content_mode_config_and_formatter() {
+        let config = Config::default();
+        let key = |content, mode, formatter, config: &Config| {
+            cache_key(content, "rust", mode, None, formatter, config)
+        };
+        let base = key("a", OperationMode::Format, "rustfmt:1.0", &config);
+        assert_eq!(
+            base,
+            key("a", OperationMode::Format, "rustfmt:1.0", &config)
+        );
+        let other_config: Config = toml::from_str("[strip]\nvalidate = false\n").unwrap();
+        for changed in [
+            key("b", OperationMode::Format, "rustfmt:1.0", &config),
+            key("a", OperationMode::All, "rustfmt:1.0", &config),
+            key("a", OperationMode::Format, "rustfmt:1.1", &config),
+            key("a", OperationMode::Format, "rustfmt:1.0", &other_config),
+        ] {
+            assert_ne!(base, changed);
+        }
+    }
+}
@@ -1,109 +1,354 @@
-use anyhow::{Context, Result, anyhow};
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::process::{Command, Stdio};
-
-const ASTYLE: &str = "astyle";
-const RUSTFMT: &str = "rustfmt";
-const RUBOCOP: &str = "rubocop";
-const TAPLO: &str = "taplo";
-const PRETTIERD: &str = "prettierd";
-const ASMFMT: &str = "asmfmt";
-const CRYSTAL: &str = "crystal";
-const FISH_INDENT: &str = "fish_indent";
-const SHFMT: &str = "shfmt";
-const STYLUA: &str = "stylua";
-const BLACK: &str = "black";
-const PERLTIDY: &str = "perltidy";
-const GOFMT: &str = "gofmt";
-const ELM_FORMAT: &str = "elm-format";
-const ORMOLU: &str = "ormolu";
-const CABAL_FMT: &str = "cabal-fmt";
-const TIDY: &str = "tidy";
-const KTLINT: &str = "ktlint";
-const GOOGLE_JAVA_FORMAT: &str = "google-java-format";
-const SWIFT_FORMAT: &str = "swift-format";
-const DOCKFMT: &str = "dockfmt";
-const NGINXFMT: &str = "nginxfmt";
-const NIXFMT: &str = "nixfmt";
-const DJLINT: &str = "djlint";
-
-fn run_formatter(tool: &str, base_args: &[&str], file_path: &Path) -> Result<()> {
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ToolError {
+    #[error("No formatter available for {lang} (tried: {})", .tried.join(", "))]
+    Unavailable { lang: String, tried: Vec<String> },
+    #[error("Formatter '{tool}' cannot format line ranges of {lang}")]
+    RangesUnsupported { lang: String, tool: String },
+    #[error("Failed to spawn formatter '{tool}': {source}")]
+    Spawn { tool: String, source: io::Error },
+    #[error("Formatter '{tool}' timed out after {}s and was killed", .timeout.as_secs_f64())]
+    TimedOut { tool: String, timeout: Duration },
+    #[error("Formatter '{tool}' failed{}:\n{stderr}", .code.map(|c| format!(" with exit code {}", c)).unwrap_or_default())]
+    Rejected {
+        tool: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    #[error("I/O error while running formatter '{tool}': {source}")]
+    Io { tool: String, source: io::Error },
+}
+
+impl ToolError {
+    pub fn tool(&self) -> Option<&str> {
+        match self {
+            ToolError::Unavailable { .. } => None,
+            ToolError::RangesUnsupported { tool, .. }
+            | ToolError::Spawn { tool, .. }
+            | ToolError::TimedOut { tool, .. }
+            | ToolError::Rejected { tool, .. }
+            | ToolError::Io { tool, .. } => Some(tool),
+        }
+    }
+
+    pub fn with_tool(mut self, name: String) -> Self {
+        match &mut self {
+            ToolError::Unavailable { .. } => {}
+            ToolError::RangesUnsupported { tool, .. }
+            | ToolError::Spawn { tool, .. }
+            | ToolError::TimedOut { tool, .. }
+            | ToolError::Rejected { tool, .. }
+            | ToolError::Io { tool, .. } => *tool = name,
+        }
+        self
+    }
+}
+
+fn io_error(tool: &str) -> impl Fn(io::Error) -> ToolError + '_ {
+    move |source| ToolError::Io {
+        tool: tool.to_string(),
+        source,
+    }
+}
+
+fn spawn(cmd: &mut Command, tool: &str) -> Result<Child, ToolError> {
+    cmd.spawn().map_err(|source| ToolError::Spawn {
+        tool: tool.to_string(),
+        source,
+    })
+}
+
+fn rejected(tool: &str, status: ExitStatus, output: &[&[u8]]) -> ToolError {
+    let stderr = output
+        .iter()
+        .map(|text| String::from_utf8_lossy(text).trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    ToolError::Rejected {
+        tool: tool.to_string(),
+        code: status.code(),
+        stderr,
+    }
+}
+
+/// Formatters run in their own process group so a timeout can kill everything
+/// they started. That also takes them out of the terminal's foreground group,
+/// so SIGINT/SIGTERM are forwarded to the groups registered here.
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    const SLOTS: usize = 64;
+    const RESERVED: i32 = -1;
+
+    static GROUPS: [AtomicI32; SLOTS] = [const { AtomicI32::new(0) }; SLOTS];
+    static INSTALL: Once = Once::new();
+
+    extern "C" fn forward(signal: libc::c_int) {
+        for slot in &GROUPS {
+            let pgid = slot.load(Ordering::SeqCst);
+            if pgid > 0 {
+                // SAFETY: `kill`, `signal` and `raise` are async-signal-safe
+                // and only take integers.
+                unsafe { libc::kill(-pgid, libc::SIGKILL) };
+            }
+        }
+        // SAFETY: see above; restoring the default action and re-raising
+        // terminates the process the way the signal would have.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    fn install() {
+        INSTALL.call_once(|| {
+            for signal in [libc::SIGINT, libc::SIGTERM] {
+                let handler = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                // SAFETY: `forward` is an `extern "C"` handler that only calls
+                // async-signal-safe functions. A signal the parent ignored
+                // (e.g. under `nohup`) stays ignored.
+                unsafe {
+                    if libc::signal(signal, handler) == libc::SIG_IGN {
+                        libc::signal(signal, libc::SIG_IGN);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Reserves a slot for a new group, or `None` when all slots are taken and
+    /// the child should stay in the foreground group instead.
+    pub fn reserve() -> Option<usize> {
+        install();
+        GROUPS.iter().position(|slot| {
+            slot.compare_exchange(0, RESERVED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+    }
+
+    pub fn track(slot: usize, pid: u32) {
+        GROUPS[slot].store(pid as i32, Ordering::SeqCst);
+    }
+
+    pub fn release(slot: usize) {
+        GROUPS[slot].store(0, Ordering::SeqCst);
+    }
+}
+
+struct ProcessGroup {
+    #[cfg(unix)]
+    slot: Option<usize>,
+}
+
+impl ProcessGroup {
+    fn track(&self, child: &Child) {
+        #[cfg(unix)]
+        if let Some(slot) = self.slot {
+            interrupt::track(slot, child.id());
+        }
+        #[cfg(not(unix))]
+        let _ = child;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(slot) = self.slot {
+            interrupt::release(slot);
+        }
+    }
+}
+
+fn new_process_group(cmd: &mut Command) -> ProcessGroup {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let slot = interrupt::reserve();
+        if slot.is_some() {
+            cmd.process_group(0);
+        }
+        ProcessGroup { slot }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+        ProcessGroup {}
+    }
+}
+
+fn kill_process_group(child: &mut Child, group: &ProcessGroup) {
+    #[cfg(unix)]
+    if group.slot.is_some() {
+        // SAFETY: `kill` only takes integers and has no memory-safety
+        // preconditions. The child leads its own process group (see
+        // `new_process_group`) and has not been reaped, so `-pid` still names it.
+        let result = unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        if result != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                eprintln!(
+                    "Warning: Failed to kill process group {}: {}",
+                    child.id(),
+                    err
+                );
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = group;
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn wait_with_timeout(
+    mut child: Child,
+    group: ProcessGroup,
+    tool: &str,
+    timeout: Option<Duration>,
+) -> Result<Output, ToolError> {
+    group.track(&child);
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(io_error(tool))? {
+            break status;
+        }
+        if let (Some(deadline), Some(timeout)) = (deadline, timeout)
+            && Instant::now() >= deadline
+        {
+            kill_process_group(&mut child, &group);
+            return Err(ToolError::TimedOut {
+                tool: tool.to_string(),
+                timeout,
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn status_ok(status: ExitStatus, success_codes: &[i32]) -> bool {
+    status
+        .code()
+        .is_some_and(|code| success_codes.contains(&code))
+}
+
+pub fn run_formatter(
+    tool: &str,
+    args: &[String],
+    success_codes: &[i32],
+    timeout: Option<Duration>,
+) -> Result<(), ToolError> {
     let mut cmd = Command::new(tool);
-    cmd.args(base_args);
-    cmd.arg(file_path);
+    cmd.args(args);
+    let group = new_process_group(&mut cmd);
 
     cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::null());
     cmd.stderr(Stdio::piped());
 
-    let process = cmd
-        .spawn()
-        .with_context(|| format!("Failed to spawn formatter '{}'", tool))?;
+    let process = spawn(&mut cmd, tool)?;
+    let output = wait_with_timeout(process, group, tool, timeout)?;
 
-    let output = process
-        .wait_with_output()
-        .with_context(|| format!("Formatter '{}' failed to run", tool))?;
+    if !status_ok(output.status, success_codes) {
+        return Err(rejected(tool, output.status, &[&output.stderr]));
+    }
+    Ok(())
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!(
-            "Formatter '{}' failed for {}:\n{}",
+pub fn run_formatter_batch(
+    tool: &str,
+    args: &[String],
+    success_codes: &[i32],
+    timeout: Option<Duration>,
+) -> Result<(), ToolError> {
+    let mut cmd = Command::new(tool);
+    cmd.args(args);
+    let group = new_process_group(&mut cmd);
+
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let process = spawn(&mut cmd, tool)?;
+    let output = wait_with_timeout(process, group, tool, timeout)?;
+
+    if !status_ok(output.status, success_codes) {
+        return Err(rejected(
             tool,
-            file_path.display(),
-            stderr.trim()
-        );
+            output.status,
+            &[&output.stdout, &output.stderr],
+        ));
     }
     Ok(())
 }
 
-fn run_formatter_stdin_stdout(tool: &str, base_args: &[&str], file_path: &Path) -> Result<()> {
-    let input_content = std::fs::read(file_path).with_context(|| {
-        format!(
-            "Failed to read file {} for stdin formatter",
-            file_path.display()
-        )
-    })?;
+pub fn run_formatter_stdin_stdout(
+    tool: &str,
+    args: &[String],
+    file_path: &Path,
+    success_codes: &[i32],
+    timeout: Option<Duration>,
+) -> Result<(), ToolError> {
+    let input_content = std::fs::read(file_path).map_err(io_error(tool))?;
 
     let mut cmd = Command::new(tool);
-    cmd.args(base_args);
+    cmd.args(args);
+    let group = new_process_group(&mut cmd);
 
     cmd.stdin(Stdio::piped());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
-    let mut process = cmd
-        .spawn()
-        .with_context(|| format!("Failed to spawn stdin formatter '{}'", tool))?;
+    let mut process = spawn(&mut cmd, tool)?;
 
-    let mut stdin = process
-        .stdin
-        .take()
-        .ok_or_else(|| anyhow!("Failed to open stdin for {}", tool))?;
+    let mut stdin = process.stdin.take().ok_or_else(|| ToolError::Io {
+        tool: tool.to_string(),
+        source: io::Error::other("stdin was not captured"),
+    })?;
 
     let write_thread = std::thread::spawn(move || {
         let _ = stdin.write_all(&input_content);
     });
 
-    let output = process
-        .wait_with_output()
-        .with_context(|| format!("Stdin formatter '{}' failed to run", tool))?;
+    let output = wait_with_timeout(process, group, tool, timeout)?;
 
     let _ = write_thread.join();
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!(
-            "Stdin formatter '{}' failed for {}:\n{}",
-            tool,
-            file_path.display(),
-            stderr.trim()
-        );
+    if !status_ok(output.status, success_codes) {
+        return Err(rejected(tool, output.status, &[&output.stderr]));
     }
 
     let parent_dir = file_path
         .parent()
-        .ok_or_else(|| anyhow!("Failed to get parent dir for {}", file_path.display()))?;
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
     let mut temp_file = tempfile::Builder::new()
         .prefix(&format!(
             ".{}_fmt_",
@@ -114,145 +359,74 @@ fn run_formatter_stdin_stdout(tool: &str, base_args: &[&str], file_path: &Path)
         ))
         .suffix(".tmp")
         .tempfile_in(parent_dir)
-        .with_context(|| format!("Failed to create temp file for writing {} output", tool))?;
+        .map_err(io_error(tool))?;
 
     temp_file
         .write_all(&output.stdout)
-        .with_context(|| format!("Failed to write {} output to temp file", tool))?;
-
-    temp_file.persist(file_path).map_err(|persist_error| {
-        anyhow!(
-            "Failed to overwrite original file {} with temp file {}: {}",
-            file_path.display(),
-            persist_error.file.path().display(),
-            persist_error.error
-        )
-    })?;
+        .map_err(io_error(tool))?;
 
-    Ok(())
-}
+    temp_file
+        .persist(file_path)
+        .map_err(|persist_error| io_error(tool)(persist_error.error))?;
 
-pub fn run_rustfmt(file_path: &Path) -> Result<()> {
-    run_formatter(RUSTFMT, &[], file_path)
-        .with_context(|| format!("rustfmt failed for {}", file_path.display()))
+    Ok(())
 }
 
-pub fn run_astyle(file_path: &Path, lang: &str) -> Result<()> {
-    let style_arg = match lang {
-        "c" => "--style=kr",
-        "cpp" => "--style=google",
-        _ => "--style=kr",
+pub fn tool_version(tool: &str, version_args: &[String]) -> Option<String> {
+    let mut cmd = Command::new(tool);
+    cmd.args(version_args);
+    let group = new_process_group(&mut cmd);
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let process = spawn(&mut cmd, tool).ok()?;
+    let output = wait_with_timeout(process, group, tool, Some(VERSION_TIMEOUT)).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = if output.stdout.is_empty() {
+        output.stderr
+    } else {
+        output.stdout
     };
-    run_formatter(ASTYLE, &[style_arg, "-n"], file_path)
-        .with_context(|| format!("astyle failed for {}", file_path.display()))
-}
-
-pub fn run_rubocop_autocorrect(file_path: &Path) -> Result<()> {
-    run_formatter(RUBOCOP, &["-A", "--fail-level", "error"], file_path)
-        .with_context(|| format!("rubocop failed for {}", file_path.display()))
-}
-
-pub fn run_taplo_fmt(file_path: &Path) -> Result<()> {
-    run_formatter(TAPLO, &["fmt"], file_path)
-        .with_context(|| format!("taplo fmt failed for {}", file_path.display()))
-}
-
-pub fn run_prettierd_fmt(file_path: &Path) -> Result<()> {
-    run_formatter(PRETTIERD, &[], file_path)
-        .with_context(|| format!("prettierd failed for {}", file_path.display()))
-}
-
-pub fn run_asmfmt(file_path: &Path) -> Result<()> {
-    run_formatter(ASMFMT, &[], file_path)
-        .with_context(|| format!("asmfmt failed for {}", file_path.display()))
-}
-
-pub fn run_crystal_format(file_path: &Path) -> Result<()> {
-    run_formatter_stdin_stdout(CRYSTAL, &["tool", "format", "-"], file_path)
-        .with_context(|| format!("crystal tool format failed for {}", file_path.display()))
-}
-
-pub fn run_fish_indent(file_path: &Path) -> Result<()> {
-    run_formatter(FISH_INDENT, &["-w"], file_path)
-        .with_context(|| format!("fish_indent failed for {}", file_path.display()))
-}
-
-pub fn run_shfmt(file_path: &Path) -> Result<()> {
-    run_formatter(SHFMT, &["-w", "-i", "4", "-ci"], file_path)
-        .with_context(|| format!("shfmt failed for {}", file_path.display()))
+    Some(
+        String::from_utf8_lossy(&text)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    )
 }
 
-pub fn run_stylua(file_path: &Path) -> Result<()> {
-    run_formatter(STYLUA, &[], file_path)
-        .with_context(|| format!("stylua failed for {}", file_path.display()))
-}
-
-pub fn run_black(file_path: &Path) -> Result<()> {
-    run_formatter(BLACK, &["-q"], file_path)
-        .with_context(|| format!("black failed for {}", file_path.display()))
-}
-
-pub fn run_perltidy(file_path: &Path) -> Result<()> {
-    run_formatter_stdin_stdout(PERLTIDY, &["-st"], file_path)
-        .with_context(|| format!("perltidy failed for {}", file_path.display()))
-}
-
-pub fn run_gofmt(file_path: &Path) -> Result<()> {
-    run_formatter(GOFMT, &["-w"], file_path)
-        .with_context(|| format!("gofmt failed for {}", file_path.display()))
-}
-
-pub fn run_elm_format(file_path: &Path) -> Result<()> {
-    run_formatter(ELM_FORMAT, &["--yes"], file_path)
-        .with_context(|| format!("elm-format failed for {}", file_path.display()))
-}
-
-pub fn run_ormolu(file_path: &Path) -> Result<()> {
-    run_formatter(ORMOLU, &["-m", "inplace"], file_path)
-        .with_context(|| format!("ormolu failed for {}", file_path.display()))
-}
-
-pub fn run_cabal_fmt(file_path: &Path) -> Result<()> {
-    run_formatter(CABAL_FMT, &["-i"], file_path)
-        .with_context(|| format!("cabal-fmt failed for {}", file_path.display()))
-}
-
-pub fn run_tidy(file_path: &Path) -> Result<()> {
-    run_formatter(TIDY, &["-m", "-q", "-indent"], file_path)
-        .with_context(|| format!("tidy failed for {}", file_path.display()))
-}
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
 
-pub fn run_ktlint(file_path: &Path) -> Result<()> {
-    run_formatter(KTLINT, &["-F"], file_path)
-        .with_context(|| format!("ktlint failed for {}", file_path.display()))
-}
-
-pub fn run_google_java_format(file_path: &Path) -> Result<()> {
-    run_formatter(GOOGLE_JAVA_FORMAT, &["-i"], file_path)
-        .with_context(|| format!("google-java-format failed for {}", file_path.display()))
-}
-
-pub fn run_swift_format(file_path: &Path) -> Result<()> {
-    run_formatter(SWIFT_FORMAT, &["format", "--in-place"], file_path)
-        .with_context(|| format!("swift-format failed for {}", file_path.display()))
-}
-
-pub fn run_dockfmt(file_path: &Path) -> Result<()> {
-    run_formatter(DOCKFMT, &["format", "-w"], file_path)
-        .with_context(|| format!("dockfmt failed for {}", file_path.display()))
-}
-
-pub fn run_nginxfmt(file_path: &Path) -> Result<()> {
-    run_formatter(NGINXFMT, &[], file_path)
-        .with_context(|| format!("nginxfmt failed for {}", file_path.display()))
-}
-
-pub fn run_nixfmt(file_path: &Path) -> Result<()> {
-    run_formatter(NIXFMT, &[], file_path)
-        .with_context(|| format!("nixfmt failed for {}", file_path.display()))
-}
+    fn is_running(pid: &str) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+            !stat
+                .rsplit(')')
+                .next()
+                .unwrap_or_default()
+                .trim_start()
+                .starts_with('Z')
+        })
+    }
 
-pub fn run_djlint(file_path: &Path) -> Result<()> {
-    run_formatter(DJLINT, &["--reformat"], file_path)
-        .with_context(|| format!("djlint failed for {}", file_path.display()))
+    #[test]
+    fn timeout_kills_grandchildren() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("sleep 60 & echo $! > '{}'; sleep 60", pid_file.display());
+        let args = ["-c".to_string(), script];
+        let result = run_formatter("sh", &args, &[0], Some(Duration::from_millis(500)));
+        assert!(matches!(result, Err(ToolError::TimedOut { .. })));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while is_running(pid.trim()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!is_running(pid.trim()), "sleep {} survived", pid.trim());
+    }
 }
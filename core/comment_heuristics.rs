@@ -0,0 +1,593 @@
+const CODE_THRESHOLD: f32 = 0.5;
+
+const C_FAMILY_KEYWORDS: &[&str] = &[
+    "if", "else", "for", "while", "return", "break", "continue", "switch", "case", "const",
+    "static", "int", "char", "void", "struct", "typedef", "include", "define", "var", "let",
+    "function", "import", "export", "class", "new", "this", "public", "private", "package", "func",
+    "go", "defer", "val", "fun", "await", "async", "try", "catch", "throw",
+];
+const RUST_KEYWORDS: &[&str] = &[
+    "let",
+    "fn",
+    "pub",
+    "use",
+    "mod",
+    "impl",
+    "struct",
+    "enum",
+    "match",
+    "if",
+    "else",
+    "for",
+    "while",
+    "loop",
+    "return",
+    "mut",
+    "const",
+    "static",
+    "trait",
+    "where",
+    "async",
+    "await",
+    "unsafe",
+    "println!",
+    "assert!",
+    "assert_eq!",
+    "dbg!",
+    "todo!",
+    "self",
+    "Some",
+    "None",
+    "Ok",
+    "Err",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "def", "class", "import", "from", "return", "if", "elif", "else", "for", "while", "with",
+    "try", "except", "raise", "lambda", "yield", "pass", "print", "self", "async", "await",
+    "assert", "del", "global",
+];
+const RUBY_KEYWORDS: &[&str] = &[
+    "def",
+    "end",
+    "class",
+    "module",
+    "require",
+    "require_relative",
+    "if",
+    "elsif",
+    "else",
+    "unless",
+    "do",
+    "puts",
+    "return",
+    "yield",
+    "attr_reader",
+    "attr_accessor",
+    "include",
+    "private",
+    "self",
+    "raise",
+    "rescue",
+    "begin",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "fi", "for", "do", "done", "case", "esac", "echo", "export", "local", "function",
+    "return", "set", "source", "cd", "exit", "printf", "read", "shift", "RUN", "FROM", "COPY",
+    "CMD", "ENV", "my", "sub", "use", "end", "else",
+];
+const LUA_KEYWORDS: &[&str] = &[
+    "local", "function", "end", "if", "then", "else", "elseif", "for", "while", "do", "return",
+    "require", "print", "repeat", "until",
+];
+const HASKELL_KEYWORDS: &[&str] = &[
+    "import", "module", "where", "let", "in", "case", "of", "if", "then", "else", "data", "type",
+    "newtype", "instance", "class", "do",
+];
+const NIX_KEYWORDS: &[&str] = &[
+    "let", "in", "with", "inherit", "rec", "import", "if", "then", "else",
+];
+const PROSE_MARKERS: &[&str] = &[
+    "TODO", "FIXME", "NOTE", "HACK", "XXX", "SAFETY", "README", "EXPLAIN",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommentClassification {
+    pub score: f32,
+    pub is_code: bool,
+}
+
+pub fn language_keywords(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "rust" => RUST_KEYWORDS,
+        "python" => PYTHON_KEYWORDS,
+        "ruby" | "crystal" => RUBY_KEYWORDS,
+        "shell" | "bash" | "fish" | "perl" | "dockerfile" | "conf" => SHELL_KEYWORDS,
+        "lua" => LUA_KEYWORDS,
+        "haskell" | "elm" => HASKELL_KEYWORDS,
+        "nix" => NIX_KEYWORDS,
+        _ => C_FAMILY_KEYWORDS,
+    }
+}
+
+pub fn comment_body(text: &str) -> String {
+    let mut body = text.trim();
+    for (open, close) in [
+        ("<!--", "-->"),
+        ("--[[", "]]"),
+        ("/*", "*/"),
+        ("{-", "-}"),
+        ("{#", "#}"),
+    ] {
+        if let Some(inner) = body.strip_prefix(open) {
+            body = inner.strip_suffix(close).unwrap_or(inner);
+            break;
+        }
+    }
+    body.lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(['/', '#', '!', '-', '*', ';'])
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '!'))
+        .filter(|w| !w.is_empty())
+}
+
+fn brackets_balanced(line: &str) -> bool {
+    let mut stack = Vec::new();
+    for c in line.chars() {
+        match c {
+            '(' | '[' | '{' => stack.push(c),
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if stack.pop() != Some(expected) {
+                    return false;
+                }
+            }
+            _ => {}
+        }
+    }
+    stack.is_empty()
+}
+
+fn has_call_pattern(line: &str) -> bool {
+    line.match_indices('(').any(|(i, _)| {
+        line[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '!')
+    })
+}
+
+fn has_assignment_pattern(line: &str) -> bool {
+    ["+=", "-=", "*=", "/=", ":=", "<-", "=>", "->", "::"]
+        .iter()
+        .any(|op| line.contains(op))
+        || line.match_indices('=').any(|(i, _)| {
+            let before = line[..i].trim_end();
+            let after = line[i + 1..].trim_start();
+            !before.is_empty()
+                && !after.is_empty()
+                && !after.starts_with('=')
+                && !before.ends_with(['=', '!', '<', '>'])
+                && before
+                    .chars()
+                    .next_back()
+                    .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == ']' || c == ')')
+        })
+}
+
+fn line_score(line: &str, keywords: &[&str]) -> f32 {
+    let line = line.trim();
+    if line.is_empty() {
+        return 0.0;
+    }
+    let first_word = words(line).next().unwrap_or("");
+    if PROSE_MARKERS
+        .iter()
+        .any(|m| first_word.eq_ignore_ascii_case(m))
+    {
+        return 0.0;
+    }
+
+    let mut score: f32 = 0.0;
+    if line.ends_with([';', '{', '}', ')', ']', ',']) {
+        score += 0.35;
+    }
+    if has_call_pattern(line) {
+        score += 0.25;
+    }
+    if has_assignment_pattern(line) {
+        score += 0.25;
+    }
+    if keywords.contains(&first_word) {
+        score += 0.3;
+    }
+    if first_word.chars().next().is_some_and(char::is_lowercase)
+        && !line.ends_with(['.', '?', '!', ':'])
+    {
+        score += 0.2;
+    }
+    if line.contains("==") || line.contains("&&") || line.contains("||") {
+        score += 0.1;
+    }
+    if !brackets_balanced(line) {
+        score -= 0.2;
+    }
+
+    let word_count = words(line).count();
+    let symbol_count = line
+        .chars()
+        .filter(|c| !(c.is_alphanumeric() || c.is_whitespace()))
+        .count();
+    if word_count >= 4 && symbol_count <= 1 {
+        score -= 0.4;
+    }
+    if line.ends_with(['.', '?', '!', ':']) && !line.ends_with("..") {
+        score -= 0.2;
+    }
+    if first_word.chars().next().is_some_and(char::is_uppercase)
+        && line.contains(' ')
+        && symbol_count <= 2
+    {
+        score -= 0.15;
+    }
+    score.clamp(0.0, 1.0)
+}
+
+pub fn classify_comment(text: &str, lang: &str) -> CommentClassification {
+    if is_doc_comment(text) {
+        return CommentClassification {
+            score: 0.0,
+            is_code: false,
+        };
+    }
+    let body = comment_body(text);
+    let keywords = language_keywords(lang);
+    let scores: Vec<f32> = body.lines().map(|l| line_score(l, keywords)).collect();
+    let score = if scores.is_empty() {
+        0.0
+    } else {
+        scores.iter().sum::<f32>() / scores.len() as f32
+    };
+    CommentClassification {
+        score,
+        is_code: score >= CODE_THRESHOLD,
+    }
+}
+
+const REDUNDANT_THRESHOLD: f32 = 0.6;
+const MAX_REDUNDANT_WORDS: usize = 8;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "the", "to", "of", "this", "that", "is", "are", "be", "and", "or", "for", "in",
+    "on", "at", "by", "with", "from", "into", "it", "its", "we", "our", "here", "now", "then",
+    "value", "variable", "new", "current", "given", "all", "some", "up", "out",
+];
+const WHY_SIGNALS: &[&str] = &[
+    "because",
+    "since",
+    "workaround",
+    "hack",
+    "issue",
+    "bug",
+    "why",
+    "otherwise",
+    "must",
+    "don't",
+    "dont",
+    "never",
+    "ensure",
+    "required",
+    "requires",
+    "avoid",
+    "note",
+    "todo",
+    "fixme",
+    "safety",
+    "xxx",
+    "warning",
+    "careful",
+    "unless",
+    "although",
+    "though",
+    "however",
+    "due",
+];
+const WHY_PHRASES: &[&str] = &[
+    "http://",
+    "https://",
+    "so that",
+    "in order to",
+    "see ",
+    "do not",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedundancyClassification {
+    pub score: f32,
+    pub is_redundant: bool,
+}
+
+pub fn is_doc_comment(text: &str) -> bool {
+    let text = text.trim_start();
+    (text.starts_with("///") && !text.starts_with("////"))
+        || text.starts_with("//!")
+        || (text.starts_with("/**") && !text.starts_with("/**/"))
+        || text.starts_with("/*!")
+        || text.starts_with("-- |")
+        || text.starts_with("{-|")
+        || text.starts_with("--- ")
+}
+
+pub fn has_why_signal(text: &str) -> bool {
+    let lower = text.to_lowercase();
+    WHY_PHRASES.iter().any(|p| lower.contains(p))
+        || words(&lower).any(|w| WHY_SIGNALS.contains(&w.trim_end_matches('!')))
+        || lower.match_indices('#').any(|(i, _)| {
+            lower[i + 1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_digit())
+        })
+}
+
+fn stem(word: &str) -> String {
+    let word = word.to_lowercase();
+    for suffix in ["ing", "es", "ed", "s"] {
+        if let Some(stripped) = word.strip_suffix(suffix)
+            && stripped.len() >= 3
+        {
+            return stripped.to_string();
+        }
+    }
+    word
+}
+
+fn split_identifier(identifier: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in identifier.chars() {
+        if c == '_' || c == '!' {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+fn code_vocabulary(code: &str) -> Vec<String> {
+    let code = code.trim();
+    let mut vocabulary: Vec<String> = words(code)
+        .flat_map(split_identifier)
+        .map(|w| stem(&w))
+        .collect();
+    let mut implied = |ws: &[&str]| vocabulary.extend(ws.iter().map(|w| stem(w)));
+    if code.contains("+= 1") || code.contains("++") {
+        implied(&["increment", "increase", "add", "one"]);
+    }
+    if code.contains("-= 1") || code.contains("--") {
+        implied(&["decrement", "decrease", "subtract", "one"]);
+    }
+    if code.contains("+=") || code.contains(" + ") {
+        implied(&["add", "sum", "plus"]);
+    }
+    if code.contains('=') {
+        implied(&[
+            "set",
+            "assign",
+            "store",
+            "initialize",
+            "init",
+            "save",
+            "update",
+        ]);
+    }
+    if code.contains('(') {
+        implied(&["call", "invoke", "run", "execute", "get"]);
+    }
+    let first = words(code).next().unwrap_or("");
+    match first {
+        "return" => implied(&["return", "result"]),
+        "if" | "unless" | "elif" | "elsif" => implied(&["check", "whether", "condition"]),
+        "for" | "while" | "loop" => implied(&["loop", "iterate", "over", "each", "through"]),
+        "import" | "use" | "require" | "include" | "from" => implied(&["import", "load"]),
+        "fn" | "def" | "function" | "func" | "fun" => implied(&["function", "define", "method"]),
+        "let" | "var" | "const" | "local" | "val" => implied(&["create", "declare", "define"]),
+        "print" | "println!" | "puts" | "echo" | "printf" => implied(&["print", "output", "log"]),
+        _ => {}
+    }
+    vocabulary
+}
+
+pub fn classify_redundancy(comment: &str, code: &str) -> RedundancyClassification {
+    let not_redundant = RedundancyClassification {
+        score: 0.0,
+        is_redundant: false,
+    };
+    let body = comment_body(comment);
+    if code.trim().is_empty() || is_doc_comment(comment) || has_why_signal(&body) {
+        return not_redundant;
+    }
+    let comment_words: Vec<String> = words(&body)
+        .flat_map(split_identifier)
+        .map(|w| w.to_lowercase())
+        .filter(|w| !STOP_WORDS.contains(&w.as_str()))
+        .map(|w| stem(&w))
+        .collect();
+    if comment_words.is_empty() || comment_words.len() > MAX_REDUNDANT_WORDS {
+        return not_redundant;
+    }
+    let vocabulary = code_vocabulary(code);
+    let covered = comment_words
+        .iter()
+        .filter(|w| vocabulary.contains(w))
+        .count();
+    let score = covered as f32 / comment_words.len() as f32;
+    RedundancyClassification {
+        score,
+        is_redundant: score >= REDUNDANT_THRESHOLD,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commented_out_code_is_code() {
+        for (text, lang) in [
+            ("// let x = add(1, 2);", "rust"),
+            ("// println!(\"{}\", x);", "rust"),
+            ("/* if (ready) { start(); } */", "c"),
+            ("# result = compute(a, b)", "python"),
+            ("# import os", "python"),
+            ("-- local count = 0", "lua"),
+            ("# echo \"$PATH\"", "shell"),
+            ("// return foo.bar(baz);", "javascript"),
+        ] {
+            let c = classify_comment(text, lang);
+            assert!(c.is_code, "{text:?} scored {}", c.score);
+        }
+    }
+
+    #[test]
+    fn prose_is_not_code() {
+        for (text, lang) in [
+            ("// Parse the header before reading the body.", "rust"),
+            (
+                "// This is slow for large inputs, but it is only called once.",
+                "rust",
+            ),
+            ("# Retry a few times because the server is flaky", "python"),
+            ("// TODO: handle errors()", "c"),
+            ("// SAFETY: the pointer is valid for len bytes;", "rust"),
+            (
+                "/* Copyright 2024 The Authors. All rights reserved. */",
+                "c",
+            ),
+            ("# Die Konfiguration wird beim Start geladen.", "python"),
+        ] {
+            let c = classify_comment(text, lang);
+            assert!(!c.is_code, "{text:?} scored {}", c.score);
+        }
+    }
+
+    #[test]
+    fn doc_comments_are_never_code() {
+        for (text, lang) in [
+            ("/// let x = add(1, 2);", "rust"),
+            ("/// assert_eq!(x, 3);", "rust"),
+            ("//! use crate::prelude::*;", "rust"),
+            ("/** return compute(a, b); */", "javascript"),
+            ("-- | f x = x + 1", "haskell"),
+        ] {
+            let c = classify_comment(text, lang);
+            assert!(!c.is_code, "{text:?} scored {}", c.score);
+        }
+    }
+
+    #[test]
+    fn comments_restating_the_code_are_redundant() {
+        for (comment, code) in [
+            ("// Increment counter", "counter += 1;"),
+            ("# increment the counter", "counter += 1"),
+            ("// Return the result", "return result;"),
+            ("// Loop over all items", "for item in items {"),
+            ("// Print the name", "println!(\"{}\", name);"),
+            ("// Set x to 5", "x = 5;"),
+            ("/* Import os */", "import os"),
+            ("// Call the parseConfig function", "parseConfig(path);"),
+        ] {
+            let r = classify_redundancy(comment, code);
+            assert!(
+                r.is_redundant,
+                "{comment:?} above {code:?} scored {}",
+                r.score
+            );
+        }
+    }
+
+    #[test]
+    fn comments_explaining_why_are_kept() {
+        for (comment, code) in [
+            (
+                "// Increment counter because the API is 1-based",
+                "counter += 1;",
+            ),
+            ("// Workaround for issue #123", "counter += 1;"),
+            ("// See https://example.com/bug", "counter += 1;"),
+            (
+                "// Must run before init, otherwise the cache is empty",
+                "warm_cache();",
+            ),
+            (
+                "// Retry once so that flaky networks recover",
+                "retry(request);",
+            ),
+            (
+                "// Counter starts at one to skip the header row",
+                "counter += 1;",
+            ),
+            ("/// Increments the counter", "counter += 1;"),
+            ("// Increment counter", ""),
+        ] {
+            let r = classify_redundancy(comment, code);
+            assert!(
+                !r.is_redundant,
+                "{comment:?} above {code:?} scored {}",
+                r.score
+            );
+        }
+    }
+
+    #[test]
+    fn markers_are_never_redundant() {
+        for marker in ["TODO", "FIXME", "SAFETY", "XXX", "HACK", "NOTE", "todo"] {
+            for comment in [
+                format!("// {marker}: increment counter"),
+                format!("// {marker}(alice) increment counter"),
+                format!("# {marker} increment counter"),
+            ] {
+                let r = classify_redundancy(&comment, "counter += 1");
+                assert!(!r.is_redundant, "{comment:?} scored {}", r.score);
+            }
+        }
+    }
+
+    #[test]
+    fn non_english_comments_are_kept() {
+        for (comment, code) in [
+            ("// Zähler erhöhen", "counter += 1;"),
+            ("// incrementar el contador", "counter += 1;"),
+            ("// カウンターを増やす", "counter += 1;"),
+            ("# Счётчик увеличить", "counter += 1"),
+            ("// 计数器加一", "counter += 1;"),
+        ] {
+            let r = classify_redundancy(comment, code);
+            assert!(!r.is_redundant, "{comment:?} scored {}", r.score);
+        }
+    }
+}
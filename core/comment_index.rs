@@ -0,0 +1,327 @@
+use crate::comment_heuristics;
+use crate::config::{Config, ConfigResolver};
+use crate::languages::language_for_path;
+use crate::stripper::{self, CommentMatch};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentKind {
+    Doc,
+    Line,
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CommentsFormat {
+    #[default]
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+    Tree,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentRecord {
+    pub path: PathBuf,
+    pub language: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub kind: CommentKind,
+    pub tag: Option<String>,
+    pub text: String,
+    pub children: Vec<CommentRecord>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommentFilter {
+    pub grep: Option<Regex>,
+    pub kinds: Vec<CommentKind>,
+    pub tags: Vec<String>,
+}
+
+impl CommentFilter {
+    fn matches(&self, record: &CommentRecord) -> bool {
+        let grep_ok = self
+            .grep
+            .as_ref()
+            .is_none_or(|re| re.is_match(&record.text));
+        let kind_ok = self.kinds.is_empty() || self.kinds.contains(&record.kind);
+        let tag_ok = self.tags.is_empty()
+            || record
+                .tag
+                .as_ref()
+                .is_some_and(|t| self.tags.iter().any(|f| f.eq_ignore_ascii_case(t)));
+        grep_ok && kind_ok && tag_ok
+    }
+}
+
+pub fn comment_kind(text: &str) -> CommentKind {
+    let text = text.trim_start();
+    if comment_heuristics::is_doc_comment(text) {
+        CommentKind::Doc
+    } else if ["/*", "{-", "--[[", "<!--", "{#"]
+        .iter()
+        .any(|open| text.starts_with(open))
+    {
+        CommentKind::Block
+    } else {
+        CommentKind::Line
+    }
+}
+
+pub fn comment_tag(text: &str) -> Option<String> {
+    let body = comment_heuristics::comment_body(text);
+    let first = body.split_whitespace().next()?;
+    let word = first.trim_end_matches(':');
+    let word = word.split('(').next().unwrap_or(word);
+    let is_tag = word.len() >= 2
+        && word.chars().all(|c| c.is_ascii_uppercase())
+        && (first.ends_with(':') || first.contains('(') || body.split_whitespace().count() == 1);
+    is_tag.then(|| word.to_string())
+}
+
+fn line_and_column(input: &str, pos: usize) -> (usize, usize) {
+    let before = &input[..pos];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, input[line_start..pos].chars().count() + 1)
+}
+
+fn nest_records(
+    records: Vec<CommentRecord>,
+    input: &str,
+    matches: &[CommentMatch],
+) -> Vec<CommentRecord> {
+    let mut nested: Vec<CommentRecord> = Vec::new();
+    let mut group_end: Option<usize> = None;
+    for (record, m) in records.into_iter().zip(matches) {
+        let own_line = input[..m.from]
+            .rsplit('\n')
+            .next()
+            .is_some_and(|before| before.trim().is_empty());
+        let continues_group = own_line
+            && record.tag.is_none()
+            && group_end.is_some_and(|end| {
+                record.kind == CommentKind::Line
+                    && input[end..m.from].trim().is_empty()
+                    && input[end..m.from].matches('\n').count() == 1
+            });
+        match nested.last_mut() {
+            Some(parent) if continues_group && parent.kind == CommentKind::Line => {
+                parent.end_line = record.line;
+                parent.children.push(record);
+            }
+            _ => nested.push(record),
+        }
+        group_end = own_line.then_some(m.to);
+    }
+    nested
+}
+
+pub fn comments_in_content(content: &str, lang: &str, path: &Path) -> Result<Vec<CommentRecord>> {
+    let matches = stripper::find_language_comments(content, lang, path)
+        .map_err(|e| anyhow::anyhow!("Comment scan failed for {}: {}", path.display(), e))?;
+    let matches: Vec<CommentMatch> = matches
+        .into_iter()
+        .filter(|m| m.from < m.to && m.to <= content.len())
+        .collect();
+    let records = matches
+        .iter()
+        .map(|m| {
+            let text = &content[m.from..m.to];
+            let (line, column) = line_and_column(content, m.from);
+            CommentRecord {
+                path: path.to_path_buf(),
+                language: lang.to_string(),
+                line,
+                column,
+                end_line: line + text.matches('\n').count(),
+                kind: comment_kind(text),
+                tag: comment_tag(text),
+                text: text.trim_end().to_string(),
+                children: Vec::new(),
+            }
+        })
+        .collect();
+    Ok(nest_records(records, content, &matches))
+}
+
+pub fn collect_comments(
+    files: &[PathBuf],
+    filter: &CommentFilter,
+    config: &Config,
+) -> Result<Vec<CommentRecord>> {
+    let mut resolver = ConfigResolver::new(config);
+    let resolved = files
+        .iter()
+        .map(|path| Ok((path, resolver.resolve(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let per_file: Vec<Result<Vec<CommentRecord>>> = resolved
+        .par_iter()
+        .map(|&(path, ref config)| {
+            let Some(lang) = language_for_path(path, config) else {
+                return Ok(Vec::new());
+            };
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            comments_in_content(&content, lang, path)
+        })
+        .collect();
+    let mut records = Vec::new();
+    for file_records in per_file {
+        for record in file_records? {
+            let mut flat = vec![record.clone()];
+            flat.extend(record.children.iter().cloned());
+            if flat.iter().any(|r| filter.matches(r)) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn one_line(text: &str) -> String {
+    text.lines().map(str::trim).collect::<Vec<_>>().join(" ")
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn kind_name(kind: CommentKind) -> &'static str {
+    match kind {
+        CommentKind::Doc => "doc",
+        CommentKind::Line => "line",
+        CommentKind::Block => "block",
+    }
+}
+
+fn render_tree(records: &[CommentRecord], root: &Path) -> String {
+    let mut by_file: BTreeMap<&Path, Vec<&CommentRecord>> = BTreeMap::new();
+    for record in records {
+        by_file
+            .entry(record.path.as_path())
+            .or_default()
+            .push(record);
+    }
+    let mut out = format!("{}\n", root.display());
+    let file_count = by_file.len();
+    for (i, (path, comments)) in by_file.into_iter().enumerate() {
+        let last_file = i + 1 == file_count;
+        let shown = match path.strip_prefix(root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => path,
+        };
+        out.push_str(&format!(
+            "{} {} ({})\n",
+            if last_file { "└──" } else { "├──" },
+            shown.display(),
+            comments.len()
+        ));
+        let file_indent = if last_file { "    " } else { "│   " };
+        for (j, record) in comments.iter().enumerate() {
+            let last = j + 1 == comments.len();
+            out.push_str(&format!(
+                "{}{} {}:{} [{}] {}\n",
+                file_indent,
+                if last { "└──" } else { "├──" },
+                record.line,
+                record.column,
+                kind_name(record.kind),
+                one_line(&record.text)
+            ));
+            let comment_indent = if last { "    " } else { "│   " };
+            for (k, child) in record.children.iter().enumerate() {
+                out.push_str(&format!(
+                    "{}{}{} {}:{} {}\n",
+                    file_indent,
+                    comment_indent,
+                    if k + 1 == record.children.len() {
+                        "└──"
+                    } else {
+                        "├──"
+                    },
+                    child.line,
+                    child.column,
+                    one_line(&child.text)
+                ));
+            }
+        }
+    }
+    out
+}
+
+pub fn render_comments(
+    records: &[CommentRecord],
+    format: CommentsFormat,
+    root: &Path,
+) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        CommentsFormat::Text => {
+            for record in records {
+                out.push_str(&format!(
+                    "{}:{}:{}: [{}] {}\n",
+                    record.path.display(),
+                    record.line,
+                    record.column,
+                    kind_name(record.kind),
+                    record.text
+                ));
+                for child in &record.children {
+                    out.push_str(&format!(
+                        "{}:{}:{}:   {}\n",
+                        child.path.display(),
+                        child.line,
+                        child.column,
+                        child.text
+                    ));
+                }
+            }
+        }
+        CommentsFormat::Json => {
+            out = serde_json::to_string_pretty(records).context("Serialize comments")?;
+            out.push('\n');
+        }
+        CommentsFormat::Jsonl => {
+            for record in records {
+                out.push_str(&serde_json::to_string(record).context("Serialize comment")?);
+                out.push('\n');
+            }
+        }
+        CommentsFormat::Csv => {
+            out.push_str("path,line,column,end_line,kind,tag,language,text\n");
+            for record in records
+                .iter()
+                .flat_map(|r| std::iter::once(r).chain(&r.children))
+            {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{},{}\n",
+                    csv_field(&record.path.display().to_string()),
+                    record.line,
+                    record.column,
+                    record.end_line,
+                    kind_name(record.kind),
+                    record.tag.as_deref().unwrap_or(""),
+                    record.language,
+                    csv_field(&record.text)
+                ));
+            }
+        }
+        CommentsFormat::Tree => out = render_tree(records, root),
+    }
+    Ok(out)
+}
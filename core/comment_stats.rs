@@ -0,0 +1,281 @@
+use crate::comment_index::{CommentKind, comment_kind};
+use crate::config::{Config, ConfigResolver};
+use crate::languages::language_for_path;
+use crate::processor::build_code_mask;
+use crate::stripper::{self, CommentMatch};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum StatsFormat {
+    #[default]
+    Table,
+    Json,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommentStats {
+    pub files: usize,
+    pub total_lines: usize,
+    pub code_lines: usize,
+    pub comment_lines: usize,
+    pub blank_lines: usize,
+    pub doc_comments: usize,
+    pub todos: usize,
+    pub comment_ratio: f64,
+}
+
+impl CommentStats {
+    fn add(&mut self, other: &CommentStats) {
+        self.files += other.files;
+        self.total_lines += other.total_lines;
+        self.code_lines += other.code_lines;
+        self.comment_lines += other.comment_lines;
+        self.blank_lines += other.blank_lines;
+        self.doc_comments += other.doc_comments;
+        self.todos += other.todos;
+        self.update_ratio();
+    }
+
+    fn update_ratio(&mut self) {
+        self.comment_ratio = if self.code_lines == 0 {
+            if self.comment_lines == 0 {
+                0.0
+            } else {
+                f64::INFINITY
+            }
+        } else {
+            self.comment_lines as f64 / self.code_lines as f64
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub language: String,
+    #[serde(flatten)]
+    pub stats: CommentStats,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsReport {
+    pub files: Vec<FileStats>,
+    pub languages: BTreeMap<String, CommentStats>,
+    pub directories: BTreeMap<PathBuf, CommentStats>,
+    pub total: CommentStats,
+}
+
+impl StatsReport {
+    pub fn files_over_threshold(&self, threshold: f64) -> Vec<&FileStats> {
+        self.files
+            .iter()
+            .filter(|f| f.stats.comment_ratio > threshold)
+            .collect()
+    }
+}
+
+fn is_todo(text: &str) -> bool {
+    ["TODO", "FIXME", "XXX"]
+        .iter()
+        .any(|tag| text.contains(tag))
+}
+
+pub fn content_stats(content: &str, lang: &str) -> Result<CommentStats> {
+    let matches = stripper::find_language_comments(content, lang, Path::new(""))
+        .map_err(|e| anyhow::anyhow!("Comment scan failed: {}", e))?;
+    let matches: Vec<CommentMatch> = matches
+        .into_iter()
+        .filter(|m| m.from < m.to && m.to <= content.len())
+        .collect();
+    let code_mask = build_code_mask(content, &matches);
+
+    let mut stats = CommentStats {
+        files: 1,
+        ..CommentStats::default()
+    };
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        stats.total_lines += 1;
+        let has_code = line
+            .char_indices()
+            .any(|(i, c)| !c.is_whitespace() && code_mask[offset + i]);
+        if line.trim().is_empty() {
+            stats.blank_lines += 1;
+        } else if has_code {
+            stats.code_lines += 1;
+        } else {
+            stats.comment_lines += 1;
+        }
+        offset += line.len();
+    }
+    for m in &matches {
+        let text = &content[m.from..m.to];
+        if comment_kind(text) == CommentKind::Doc {
+            stats.doc_comments += 1;
+        }
+        if is_todo(text) {
+            stats.todos += 1;
+        }
+    }
+    stats.update_ratio();
+    Ok(stats)
+}
+
+pub fn collect_stats(files: &[PathBuf], root: &Path, config: &Config) -> Result<StatsReport> {
+    let mut resolver = ConfigResolver::new(config);
+    let resolved = files
+        .iter()
+        .map(|path| Ok((path, resolver.resolve(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let per_file: Vec<Result<Option<FileStats>>> = resolved
+        .par_iter()
+        .map(|&(path, ref config)| {
+            let Some(lang) = language_for_path(path, config) else {
+                return Ok(None);
+            };
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let stats = content_stats(&content, lang)
+                .with_context(|| format!("Failed to analyse {}", path.display()))?;
+            Ok(Some(FileStats {
+                path: path.clone(),
+                language: lang.to_string(),
+                stats,
+            }))
+        })
+        .collect();
+
+    let mut report = StatsReport::default();
+    for file in per_file {
+        let Some(file) = file? else {
+            continue;
+        };
+        report
+            .languages
+            .entry(file.language.clone())
+            .or_default()
+            .add(&file.stats);
+        let dir = file
+            .path
+            .parent()
+            .map(|p| p.strip_prefix(root).unwrap_or(p).to_path_buf())
+            .unwrap_or_default();
+        report.directories.entry(dir).or_default().add(&file.stats);
+        report.total.add(&file.stats);
+        report.files.push(file);
+    }
+    Ok(report)
+}
+
+const HEADERS: [&str; 9] = [
+    "name", "files", "lines", "code", "comments", "blank", "docs", "todos", "ratio",
+];
+
+fn stats_row(name: String, s: &CommentStats) -> [String; 9] {
+    [
+        name,
+        s.files.to_string(),
+        s.total_lines.to_string(),
+        s.code_lines.to_string(),
+        s.comment_lines.to_string(),
+        s.blank_lines.to_string(),
+        s.doc_comments.to_string(),
+        s.todos.to_string(),
+        format!("{:.2}", s.comment_ratio),
+    ]
+}
+
+fn render_table(title: &str, rows: &[[String; 9]]) -> String {
+    let mut widths = HEADERS.map(str::len);
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut out = format!("{}\n", title);
+    let header: Vec<String> = HEADERS.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(header.as_slice()).chain(rows.iter().map(|r| r.as_slice())) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, w))| {
+                if i == 0 {
+                    format!("{:<w$}", cell)
+                } else {
+                    format!("{:>w$}", cell)
+                }
+            })
+            .collect();
+        out.push_str(&format!("  {}\n", cells.join("  ")));
+    }
+    out.push('\n');
+    out
+}
+
+fn render_markdown(title: &str, rows: &[[String; 9]]) -> String {
+    let mut out = format!("## {}\n\n| {} |\n", title, HEADERS.join(" | "));
+    out.push_str(&format!("|{}\n", "---|".repeat(HEADERS.len())));
+    for row in rows {
+        out.push_str(&format!("| {} |\n", row.join(" | ")));
+    }
+    out.push('\n');
+    out
+}
+
+pub fn render_stats(report: &StatsReport, format: StatsFormat) -> Result<String> {
+    if format == StatsFormat::Json {
+        let mut out = serde_json::to_string_pretty(report).context("Serialize stats")?;
+        out.push('\n');
+        return Ok(out);
+    }
+    let sections = [
+        (
+            "Files",
+            report
+                .files
+                .iter()
+                .map(|f| stats_row(f.path.display().to_string(), &f.stats))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Languages",
+            report
+                .languages
+                .iter()
+                .map(|(lang, s)| stats_row(lang.clone(), s))
+                .collect(),
+        ),
+        (
+            "Directories",
+            report
+                .directories
+                .iter()
+                .map(|(dir, s)| {
+                    let name = if dir.as_os_str().is_empty() {
+                        ".".to_string()
+                    } else {
+                        dir.display().to_string()
+                    };
+                    stats_row(name, s)
+                })
+                .collect(),
+        ),
+        ("Total", vec![stats_row("total".to_string(), &report.total)]),
+    ];
+    let render = if format == StatsFormat::Markdown {
+        render_markdown
+    } else {
+        render_table
+    };
+    Ok(sections
+        .iter()
+        .map(|(title, rows)| render(title, rows))
+        .collect())
+}
@@ -0,0 +1,524 @@
+use crate::formatters::FormatterConfig;
+use crate::processor::{CommentStripMode, OperationMode};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "xzenfmt.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StripConfig {
+    pub enabled: Option<bool>,
+    pub comments: Option<CommentStripMode>,
+    pub validate: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriteConfig {
+    pub preserve_mtime: Option<bool>,
+}
+
+impl WriteConfig {
+    pub fn preserve_mtime(&self) -> bool {
+        self.preserve_mtime.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageConfig {
+    #[serde(deserialize_with = "one_or_many")]
+    pub formatter: Option<Vec<String>>,
+    #[serde(deserialize_with = "pipeline_steps")]
+    pub pipeline: Option<Vec<Vec<String>>>,
+    pub args: Option<Vec<String>>,
+    pub stdin: Option<bool>,
+    pub format: Option<bool>,
+    pub strip: Option<bool>,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mode: Option<OperationMode>,
+    pub skip: Option<bool>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub strip: StripConfig,
+    pub write: WriteConfig,
+    pub backup: BackupConfig,
+    pub cache: CacheConfig,
+    pub languages: BTreeMap<String, LanguageConfig>,
+    pub formatters: BTreeMap<String, FormatterConfig>,
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    #[serde(skip)]
+    pub origins: BTreeMap<String, PathBuf>,
+    #[serde(skip)]
+    pub scope: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(name) => vec![name],
+            OneOrMany::Many(names) => names,
+        }
+    }
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Some(OneOrMany::deserialize(deserializer)?.into()))
+}
+
+fn pipeline_steps<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<Vec<String>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let steps = Vec::<OneOrMany>::deserialize(deserializer)?;
+    Ok(Some(steps.into_iter().map(Vec::from).collect()))
+}
+
+struct Origins<'a> {
+    map: &'a mut BTreeMap<String, PathBuf>,
+    source: Option<&'a PathBuf>,
+}
+
+impl Origins<'_> {
+    fn set<T>(&mut self, key: &str, target: &mut T, value: Option<T>) {
+        if let Some(value) = value {
+            *target = value;
+            self.record(key);
+        }
+    }
+
+    fn record(&mut self, key: &str) {
+        if let Some(source) = self.source {
+            self.map.insert(key.to_string(), source.clone());
+        }
+    }
+}
+
+impl LanguageConfig {
+    fn merge(&mut self, other: LanguageConfig, origins: &mut Origins, prefix: &str) {
+        if other.formatter.is_some() {
+            self.args = None;
+            self.stdin = None;
+        }
+        let key = |name: &str| format!("{}.{}", prefix, name);
+        origins.set(
+            &key("formatter"),
+            &mut self.formatter,
+            other.formatter.map(Some),
+        );
+        origins.set(
+            &key("pipeline"),
+            &mut self.pipeline,
+            other.pipeline.map(Some),
+        );
+        origins.set(&key("args"), &mut self.args, other.args.map(Some));
+        origins.set(&key("stdin"), &mut self.stdin, other.stdin.map(Some));
+        origins.set(&key("format"), &mut self.format, other.format.map(Some));
+        origins.set(&key("strip"), &mut self.strip, other.strip.map(Some));
+        if !other.extensions.is_empty() {
+            self.extensions.extend(other.extensions);
+            origins.record(&key("extensions"));
+        }
+        if !other.filenames.is_empty() {
+            self.filenames.extend(other.filenames);
+            origins.record(&key("filenames"));
+        }
+    }
+}
+
+impl Config {
+    pub fn merge(&mut self, other: Config) {
+        let mut origins = Origins {
+            map: &mut self.origins,
+            source: other.sources.first(),
+        };
+        origins.set("mode", &mut self.mode, other.mode.map(Some));
+        origins.set("skip", &mut self.skip, other.skip.map(Some));
+        let include = (!other.include.is_empty()).then_some(other.include);
+        origins.set("include", &mut self.include, include);
+        let exclude = (!other.exclude.is_empty()).then_some(other.exclude);
+        origins.set("exclude", &mut self.exclude, exclude);
+        let strip = &mut self.strip;
+        origins.set(
+            "strip.enabled",
+            &mut strip.enabled,
+            other.strip.enabled.map(Some),
+        );
+        origins.set(
+            "strip.comments",
+            &mut strip.comments,
+            other.strip.comments.map(Some),
+        );
+        origins.set(
+            "strip.validate",
+            &mut strip.validate,
+            other.strip.validate.map(Some),
+        );
+        let preserve_mtime = other.write.preserve_mtime.map(Some);
+        origins.set(
+            "write.preserve_mtime",
+            &mut self.write.preserve_mtime,
+            preserve_mtime,
+        );
+        let backup = other.backup.enabled.map(Some);
+        origins.set("backup.enabled", &mut self.backup.enabled, backup);
+        let cache = other.cache.enabled.map(Some);
+        origins.set("cache.enabled", &mut self.cache.enabled, cache);
+        for (lang, lang_config) in other.languages {
+            let prefix = format!("languages.{}", lang);
+            self.languages
+                .entry(lang)
+                .or_default()
+                .merge(lang_config, &mut origins, &prefix);
+        }
+        for (name, f) in other.formatters {
+            let key = |field: &str| format!("formatters.{}.{}", name, field);
+            let target = self.formatters.entry(name.clone()).or_default();
+            origins.set(&key("command"), &mut target.command, f.command.map(Some));
+            origins.set(&key("args"), &mut target.args, f.args.map(Some));
+            origins.set(&key("stdin"), &mut target.stdin, f.stdin.map(Some));
+            let codes = f.success_codes.map(Some);
+            origins.set(&key("success_codes"), &mut target.success_codes, codes);
+            let version_args = f.version_args.map(Some);
+            origins.set(&key("version_args"), &mut target.version_args, version_args);
+            origins.set(&key("timeout"), &mut target.timeout, f.timeout.map(Some));
+            origins.set(&key("batch"), &mut target.batch, f.batch.map(Some));
+            let range_args = f.range_args.map(Some);
+            origins.set(&key("range_args"), &mut target.range_args, range_args);
+        }
+        self.sources.extend(other.sources);
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.skip.unwrap_or(false)
+    }
+
+    pub fn strip_enabled(&self) -> bool {
+        self.strip.enabled.unwrap_or(true)
+    }
+
+    pub fn strip_validate(&self) -> bool {
+        self.strip.validate.unwrap_or(true)
+    }
+
+    pub fn language(&self, lang: &str) -> Option<&LanguageConfig> {
+        self.languages.get(lang)
+    }
+
+    pub fn language_for_extension(&self, extension: &str) -> Option<&str> {
+        self.languages.iter().find_map(|(lang, c)| {
+            c.extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+                .then_some(lang.as_str())
+        })
+    }
+
+    pub fn language_for_filename(&self, file_name: &str) -> Option<&str> {
+        self.languages.iter().find_map(|(lang, c)| {
+            c.filenames
+                .iter()
+                .any(|f| f == file_name)
+                .then_some(lang.as_str())
+        })
+    }
+}
+
+pub fn load_config_file(path: &Path) -> Result<Config> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config {}", path.display()))?;
+    let mut config: Config =
+        toml::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))?;
+    config.sources.push(path.to_path_buf());
+    Ok(config)
+}
+
+pub fn global_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config_home.join("xzenfmt").join("config.toml"))
+}
+
+fn start_dir(target: &Path) -> Option<PathBuf> {
+    let absolute = std::path::absolute(target).unwrap_or_else(|_| target.to_path_buf());
+    if absolute.is_dir() {
+        Some(absolute)
+    } else {
+        absolute.parent().map(Path::to_path_buf)
+    }
+}
+
+pub fn load_config(target: &Path) -> Result<Config> {
+    let mut config = Config::default();
+    if let Some(global) = global_config_path().filter(|p| p.is_file()) {
+        config.merge(load_config_file(&global)?);
+    }
+    let scope = start_dir(target);
+    if let Some(scope) = &scope {
+        let mut chain: Vec<PathBuf> = scope
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .filter(|candidate| candidate.is_file())
+            .collect();
+        chain.reverse();
+        for path in chain {
+            config.merge(load_config_file(&path)?);
+        }
+    }
+    config.scope = scope;
+    Ok(config)
+}
+
+pub fn dir_is_skipped(dir: &Path) -> Result<bool> {
+    let candidate = dir.join(CONFIG_FILE_NAME);
+    if !candidate.is_file() {
+        return Ok(false);
+    }
+    Ok(load_config_file(&candidate)?.is_skipped())
+}
+
+pub struct ConfigResolver<'a> {
+    base: &'a Config,
+    cache: HashMap<PathBuf, Config>,
+}
+
+impl<'a> ConfigResolver<'a> {
+    pub fn new(base: &'a Config) -> Self {
+        ConfigResolver {
+            base,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, path: &Path) -> Result<Config> {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = if absolute.is_dir() {
+            absolute.as_path()
+        } else {
+            match absolute.parent() {
+                Some(dir) => dir,
+                None => return Ok(self.base.clone()),
+            }
+        };
+        self.resolve_dir(dir).cloned()
+    }
+
+    fn resolve_dir(&mut self, dir: &Path) -> Result<&Config> {
+        let inside_scope = self
+            .base
+            .scope
+            .as_deref()
+            .is_some_and(|scope| dir != scope && dir.starts_with(scope));
+        if !inside_scope {
+            return Ok(self.base);
+        }
+        if !self.cache.contains_key(dir) {
+            let mut config = match dir.parent() {
+                Some(parent) => self.resolve_dir(parent)?.clone(),
+                None => self.base.clone(),
+            };
+            let candidate = dir.join(CONFIG_FILE_NAME);
+            if candidate.is_file() {
+                config.merge(load_config_file(&candidate)?);
+            }
+            self.cache.insert(dir.to_path_buf(), config);
+        }
+        Ok(&self.cache[dir])
+    }
+}
+
+fn value_text<T: Serialize>(value: &T) -> String {
+    toml::Value::try_from(value)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "?".to_string())
+}
+
+pub fn render_config(config: &Config) -> String {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let mut push = |key: String, value: String| entries.push((key, value));
+    if let Some(mode) = &config.mode {
+        push("mode".into(), value_text(mode));
+    }
+    if let Some(skip) = config.skip {
+        push("skip".into(), skip.to_string());
+    }
+    if !config.include.is_empty() {
+        push("include".into(), value_text(&config.include));
+    }
+    if !config.exclude.is_empty() {
+        push("exclude".into(), value_text(&config.exclude));
+    }
+    if let Some(enabled) = config.strip.enabled {
+        push("strip.enabled".into(), enabled.to_string());
+    }
+    if let Some(comments) = &config.strip.comments {
+        push("strip.comments".into(), value_text(comments));
+    }
+    if let Some(validate) = config.strip.validate {
+        push("strip.validate".into(), validate.to_string());
+    }
+    if let Some(preserve_mtime) = config.write.preserve_mtime {
+        push("write.preserve_mtime".into(), preserve_mtime.to_string());
+    }
+    if let Some(enabled) = config.backup.enabled {
+        push("backup.enabled".into(), enabled.to_string());
+    }
+    if let Some(enabled) = config.cache.enabled {
+        push("cache.enabled".into(), enabled.to_string());
+    }
+    for (lang, c) in &config.languages {
+        let key = |name: &str| format!("languages.{}.{}", lang, name);
+        if let Some(formatter) = &c.formatter {
+            push(key("formatter"), value_text(formatter));
+        }
+        if let Some(pipeline) = &c.pipeline {
+            push(key("pipeline"), value_text(pipeline));
+        }
+        if let Some(args) = &c.args {
+            push(key("args"), value_text(args));
+        }
+        if let Some(stdin) = c.stdin {
+            push(key("stdin"), stdin.to_string());
+        }
+        if let Some(format) = c.format {
+            push(key("format"), format.to_string());
+        }
+        if let Some(strip) = c.strip {
+            push(key("strip"), strip.to_string());
+        }
+        if !c.extensions.is_empty() {
+            push(key("extensions"), value_text(&c.extensions));
+        }
+        if !c.filenames.is_empty() {
+            push(key("filenames"), value_text(&c.filenames));
+        }
+    }
+    for (name, f) in &config.formatters {
+        let key = |field: &str| format!("formatters.{}.{}", name, field);
+        if let Some(command) = &f.command {
+            push(key("command"), value_text(command));
+        }
+        if let Some(args) = &f.args {
+            push(key("args"), value_text(args));
+        }
+        if let Some(stdin) = f.stdin {
+            push(key("stdin"), stdin.to_string());
+        }
+        if let Some(codes) = &f.success_codes {
+            push(key("success_codes"), value_text(codes));
+        }
+        if let Some(version_args) = &f.version_args {
+            push(key("version_args"), value_text(version_args));
+        }
+        if let Some(timeout) = f.timeout {
+            push(key("timeout"), timeout.to_string());
+        }
+        if let Some(batch) = f.batch {
+            push(key("batch"), batch.to_string());
+        }
+        if let Some(range_args) = &f.range_args {
+            push(key("range_args"), value_text(range_args));
+        }
+    }
+    let mut out = String::new();
+    if config.sources.is_empty() {
+        out.push_str("# no config files found, using defaults\n");
+    }
+    for source in &config.sources {
+        out.push_str(&format!("# source: {}\n", source.display()));
+    }
+    let width = entries
+        .iter()
+        .map(|(k, v)| k.len() + v.len())
+        .max()
+        .unwrap_or(0);
+    for (key, value) in &entries {
+        let origin = config
+            .origins
+            .get(key)
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        let line = format!("{} = {}", key, value);
+        out.push_str(&format!("{:<w$}  # {}\n", line, origin, w = width + 3));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::OperationMode;
+
+    #[test]
+    fn closer_configs_override_and_record_their_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join(CONFIG_FILE_NAME);
+        let sub = dir.path().join("sub").join(CONFIG_FILE_NAME);
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(
+            &root,
+            "mode = \"strip\"\ninclude = [\"*.rs\"]\n[languages.rust]\nformatter = \"rustfmt\"\nextensions = [\"rsx\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            &sub,
+            "mode = \"format\"\n[languages.rust]\nextensions = [\"rs2\"]\n",
+        )
+        .unwrap();
+
+        let config = load_config(&dir.path().join("sub")).unwrap();
+        assert!(config.sources.ends_with(&[root.clone(), sub.clone()]));
+        assert_eq!(config.mode, Some(OperationMode::Format));
+        assert_eq!(config.origins["mode"], sub);
+        assert_eq!(config.include, ["*.rs"]);
+        assert_eq!(config.origins["include"], root);
+        let rust = config.language("rust").unwrap();
+        assert_eq!(
+            rust.formatter.as_deref(),
+            Some(&["rustfmt".to_string()][..])
+        );
+        assert_eq!(rust.extensions, ["rsx", "rs2"]);
+        assert_eq!(config.origins["languages.rust.extensions"], sub);
+
+        let top = load_config(dir.path()).unwrap();
+        assert_eq!(top.mode, Some(OperationMode::Strip));
+        assert_eq!(top.origins["mode"], root);
+    }
+}
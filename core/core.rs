@@ -1,28 +1,53 @@
+pub mod backup;
+pub mod cache;
 pub mod command_runner;
+pub mod comment_heuristics;
+pub mod comment_index;
+pub mod comment_stats;
+pub mod config;
+pub mod data_formats;
 pub mod dependency_checker;
+pub mod detect;
 pub mod file_finder;
+pub mod formatters;
+pub mod git;
+pub mod hook;
+pub mod languages;
+pub mod line_ranges;
 pub mod processor;
+pub mod report;
+pub mod safe_write;
 pub mod stripper;
 
+pub use backup::{
+    BackupManifest, BackupRun, UndoReport, format_timestamp, list_runs, state_root, undo_run,
+};
+pub use cache::{ResultCache, clear_cache};
 pub use command_runner::*;
+pub use config::{Config, ConfigResolver, LanguageConfig, load_config, render_config};
+pub use data_formats::{InternalFormatter, format_json, format_toml, format_yaml};
 pub use dependency_checker::check_dependencies;
 
-pub use file_finder::{CliArgs, Command, CompletionArgs, XzenfmtArgs, find_files};
+pub use comment_index::{
+    CommentFilter, CommentKind, CommentRecord, CommentsFormat, collect_comments, render_comments,
+};
+pub use comment_stats::{StatsFormat, StatsReport, collect_stats, render_stats};
+pub use file_finder::{
+    CacheAction, CacheArgs, CacheClearArgs, CliArgs, Command, CommentsArgs, CompletionArgs,
+    ConfigAction, ConfigArgs, ConfigShowArgs, HistoryArgs, HookAction, HookArgs, HookInstallArgs,
+    HookUninstallArgs, LanguagesArgs, SelectionArgs, StatsArgs, UndoArgs, XzenfmtArgs, find_files,
+};
+pub use formatters::{FormatterConfig, FormatterRegistry, FormatterSpec, default_formatters};
+pub use git::{GitArgs, changed_lines};
+pub use hook::{install_hook, uninstall_hook};
+pub use languages::{
+    CommentSyntax, Language, LanguageInfo, LanguagesFormat, describe_languages, render_languages,
+};
+pub use line_ranges::{LineRange, LineSelection};
 
-pub use processor::{OperationMode, ProcessedFileResult, process_files};
+pub use processor::{
+    CommentStripMode, FileMetrics, OperationMode, ProcessError, ProcessOptions,
+    ProcessedFileResult, Stage, process_content, process_files,
+};
+pub use report::{OutputFormat, RunSummary, render_report};
 pub use stripper::{StripError, find_language_comments, remove_matches};
-
-#[derive(thiserror::Error, Debug)]
-pub enum CoreError {
-    #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error),
-
-    #[error("File processing error in {path}: {message}")]
-    Processing { path: String, message: String },
-
-    #[error("Dependency check failed: {0}")]
-    Dependency(String),
-
-    #[error(transparent)]
-    Anyhow(#[from] anyhow::Error),
-}
@@ -0,0 +1,749 @@
+use crate::command_runner::ToolError;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const JSON_INDENT: &str = "  ";
+const TOML_ARRAY_INDENT: &str = "  ";
+const YAML_INDENT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InternalFormatter {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl InternalFormatter {
+    pub fn format(self, input: &str) -> Result<String> {
+        match self {
+            InternalFormatter::Json => format_json(input),
+            InternalFormatter::Toml => format_toml(input),
+            InternalFormatter::Yaml => format_yaml(input),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InternalFormatter::Json => "xzenfmt-json",
+            InternalFormatter::Toml => "xzenfmt-toml",
+            InternalFormatter::Yaml => "xzenfmt-yaml",
+        }
+    }
+
+    pub fn format_file(self, path: &Path) -> Result<(), ToolError> {
+        let io_error = |source| ToolError::Io {
+            tool: self.name().to_string(),
+            source,
+        };
+        let input = fs::read_to_string(path).map_err(io_error)?;
+        let output = self.format(&input).map_err(|e| ToolError::Rejected {
+            tool: self.name().to_string(),
+            code: None,
+            stderr: format!("{:#}", e),
+        })?;
+        if output != input {
+            fs::write(path, output).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn finish(mut out: String) -> String {
+    while out.ends_with('\n') {
+        out.pop();
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonToken<'a> {
+    Open(u8),
+    Close(u8),
+    Comma,
+    Colon,
+    Scalar(&'a str),
+    LineComment(&'a str),
+    BlockComment(&'a str),
+}
+
+struct Spanned<'a> {
+    token: JsonToken<'a>,
+    newlines_before: usize,
+}
+
+fn tokenize_json(input: &str) -> Result<Vec<Spanned<'_>>> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut stack = Vec::new();
+    let mut newlines = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let token = match bytes[i] {
+            b'\n' => {
+                newlines += 1;
+                i += 1;
+                continue;
+            }
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'{' | b'[' => {
+                stack.push(bytes[i]);
+                i += 1;
+                JsonToken::Open(bytes[start])
+            }
+            b'}' | b']' => {
+                let expected = if bytes[i] == b'}' { b'{' } else { b'[' };
+                if stack.pop() != Some(expected) {
+                    anyhow::bail!("Unexpected '{}' at byte {}", bytes[i] as char, i);
+                }
+                i += 1;
+                JsonToken::Close(bytes[start])
+            }
+            b',' => {
+                i += 1;
+                JsonToken::Comma
+            }
+            b':' => {
+                i += 1;
+                JsonToken::Colon
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\n' {
+                        anyhow::bail!("Unterminated string at byte {}", start);
+                    }
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    anyhow::bail!("Unterminated string at byte {}", start);
+                }
+                i += 1;
+                JsonToken::Scalar(&input[start..i])
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = input[i..].find('\n').map_or(bytes.len(), |n| i + n);
+                JsonToken::LineComment(input[start..i].trim_end())
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = input[i + 2..]
+                    .find("*/")
+                    .with_context(|| format!("Unterminated comment at byte {}", start))?;
+                i += end + 4;
+                JsonToken::BlockComment(&input[start..i])
+            }
+            _ => {
+                while i < bytes.len()
+                    && !bytes[i].is_ascii_whitespace()
+                    && !b"{}[],:\"".contains(&bytes[i])
+                    && !bytes[i..].starts_with(b"//")
+                    && !bytes[i..].starts_with(b"/*")
+                {
+                    i += 1;
+                }
+                JsonToken::Scalar(&input[start..i])
+            }
+        };
+        tokens.push(Spanned {
+            token,
+            newlines_before: newlines,
+        });
+        newlines = 0;
+    }
+    if !stack.is_empty() {
+        anyhow::bail!("Unclosed '{}'", *stack.last().unwrap() as char);
+    }
+    Ok(tokens)
+}
+
+fn json_value(tokens: &[Spanned]) -> Result<serde_json::Value> {
+    let code: Vec<_> = tokens
+        .iter()
+        .map(|t| t.token)
+        .filter(|t| !matches!(t, JsonToken::LineComment(_) | JsonToken::BlockComment(_)))
+        .collect();
+    if code.is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    let mut strict = String::new();
+    for (i, token) in code.iter().enumerate() {
+        match token {
+            JsonToken::Comma if matches!(code.get(i + 1), Some(JsonToken::Close(_))) => continue,
+            JsonToken::Open(b) | JsonToken::Close(b) => strict.push(*b as char),
+            JsonToken::Comma => strict.push(','),
+            JsonToken::Colon => strict.push(':'),
+            JsonToken::Scalar(s) => strict.push_str(s),
+            JsonToken::LineComment(_) | JsonToken::BlockComment(_) => {}
+        }
+        strict.push(' ');
+    }
+    Ok(serde_json::from_str(&strict)?)
+}
+
+pub fn format_json(input: &str) -> Result<String> {
+    let tokens = tokenize_json(input).context("Invalid JSON")?;
+    let original = json_value(&tokens).context("Invalid JSON")?;
+    let mut out = String::with_capacity(input.len());
+    let mut depth = 0usize;
+    let mut pending_newline = false;
+    let mut need_space = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let Spanned {
+            token,
+            newlines_before,
+        } = tokens[i];
+        let same_line = newlines_before == 0 && !out.is_empty();
+        let is_comment = matches!(
+            token,
+            JsonToken::LineComment(_) | JsonToken::BlockComment(_)
+        );
+        if matches!(token, JsonToken::Close(_)) {
+            depth = depth.saturating_sub(1);
+            pending_newline = true;
+        }
+        let was_pending = pending_newline;
+        if is_comment && same_line && !out.ends_with('\n') {
+            out.push(' ');
+            pending_newline = false;
+        } else if pending_newline {
+            if newlines_before > 1 && !matches!(token, JsonToken::Close(_)) {
+                out.push('\n');
+            }
+            out.push('\n');
+            out.push_str(&JSON_INDENT.repeat(depth));
+            pending_newline = false;
+        } else if need_space && !matches!(token, JsonToken::Comma | JsonToken::Colon) {
+            out.push(' ');
+        }
+        need_space = false;
+        match token {
+            JsonToken::Open(b) => {
+                out.push(b as char);
+                let close = if b == b'{' { b'}' } else { b']' };
+                if tokens.get(i + 1).map(|t| t.token) == Some(JsonToken::Close(close)) {
+                    out.push(close as char);
+                    i += 1;
+                } else {
+                    depth += 1;
+                    pending_newline = true;
+                }
+            }
+            JsonToken::Close(b) => out.push(b as char),
+            JsonToken::Comma => {
+                out.push(',');
+                pending_newline = true;
+            }
+            JsonToken::Colon => {
+                out.push(':');
+                need_space = true;
+            }
+            JsonToken::Scalar(s) => out.push_str(s),
+            JsonToken::LineComment(c) => {
+                out.push_str(c);
+                pending_newline = true;
+            }
+            JsonToken::BlockComment(c) => {
+                out.push_str(c);
+                if same_line && !was_pending {
+                    need_space = true;
+                } else {
+                    pending_newline = true;
+                }
+            }
+        }
+        i += 1;
+    }
+    let output = finish(out);
+    let formatted = tokenize_json(&output)
+        .and_then(|tokens| json_value(&tokens))
+        .context("Internal JSON formatter produced invalid JSON")?;
+    if formatted != original {
+        anyhow::bail!("Internal JSON formatter would change the document; leaving it untouched");
+    }
+    Ok(output)
+}
+
+#[derive(Default)]
+struct LineScan {
+    comment: Option<usize>,
+    assignment: Option<usize>,
+    brackets: isize,
+}
+
+fn scan_toml_line(line: &str, open_string: &mut Option<&'static str>) -> LineScan {
+    let bytes = line.as_bytes();
+    let mut scan = LineScan::default();
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(delim) = *open_string {
+            if bytes[i..].starts_with(delim.as_bytes()) {
+                i += delim.len();
+                while bytes[i..].starts_with(&delim.as_bytes()[..1]) {
+                    i += 1;
+                }
+                *open_string = None;
+            } else {
+                i += if bytes[i] == b'\\' && delim == "\"\"\"" {
+                    2
+                } else {
+                    1
+                };
+            }
+            continue;
+        }
+        match bytes[i] {
+            b'#' => {
+                scan.comment = Some(i);
+                break;
+            }
+            b'"' | b'\'' => {
+                let triple = if bytes[i] == b'"' { "\"\"\"" } else { "'''" };
+                if bytes[i..].starts_with(triple.as_bytes()) {
+                    *open_string = Some(triple);
+                    i += 3;
+                    continue;
+                }
+                let quote = bytes[i];
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' && quote == b'"' {
+                        2
+                    } else {
+                        1
+                    };
+                }
+            }
+            b'=' if scan.assignment.is_none() && scan.brackets == 0 => scan.assignment = Some(i),
+            b'[' | b'{' => scan.brackets += 1,
+            b']' | b'}' => scan.brackets -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    scan
+}
+
+fn with_comment(code: &str, comment: Option<&str>) -> String {
+    match comment {
+        Some(c) if code.is_empty() => c.to_string(),
+        Some(c) => format!("{} {}", code, c),
+        None => code.to_string(),
+    }
+}
+
+fn push_blank(out: &mut String) {
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+pub fn format_toml(input: &str) -> Result<String> {
+    let original: toml::Table = input.parse().context("Invalid TOML")?;
+    let mut out = String::with_capacity(input.len());
+    let mut open_string: Option<&'static str> = None;
+    let mut depth = 0isize;
+    for line in input.lines() {
+        if open_string.is_some() {
+            scan_toml_line(line, &mut open_string);
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let content = line.trim_start();
+        if content.trim_end().is_empty() {
+            push_blank(&mut out);
+            continue;
+        }
+        let scan = scan_toml_line(content, &mut open_string);
+        let (code, comment) = match scan.comment {
+            Some(at) => (content[..at].trim_end(), Some(content[at..].trim_end())),
+            None if open_string.is_some() => (content, None),
+            None => (content.trim_end(), None),
+        };
+        let code = match scan.assignment {
+            Some(at) if depth == 0 => format!(
+                "{} = {}",
+                code[..at].trim_end(),
+                code[at + 1..].trim_start()
+            ),
+            _ => code.to_string(),
+        };
+        let closes_first = code.starts_with(']') || code.starts_with('}');
+        let indent = (depth - isize::from(closes_first)).max(0) as usize;
+        let is_header = depth == 0 && code.starts_with('[');
+        if !is_header {
+            out.push_str(&TOML_ARRAY_INDENT.repeat(indent));
+        }
+        out.push_str(&with_comment(&code, comment));
+        out.push('\n');
+        if !is_header {
+            depth = (depth + scan.brackets).max(0);
+        }
+    }
+    let output = finish(out);
+    let formatted: toml::Table = output
+        .parse()
+        .context("Internal TOML formatter produced invalid TOML")?;
+    if formatted != original {
+        anyhow::bail!("Internal TOML formatter would change the document; leaving it untouched");
+    }
+    Ok(output)
+}
+
+fn yaml_documents(input: &str) -> Result<Vec<serde_yaml::Value>> {
+    serde_yaml::Deserializer::from_str(input)
+        .map(|doc| serde_yaml::Value::deserialize(doc).map_err(Into::into))
+        .collect()
+}
+
+#[derive(Default)]
+struct YamlScan {
+    comment: Option<usize>,
+    colon: Option<usize>,
+    flow: isize,
+    open_quote: Option<u8>,
+}
+
+fn scan_yaml_line(line: &str, flow: isize, open_quote: Option<u8>) -> YamlScan {
+    let bytes = line.as_bytes();
+    let mut scan = YamlScan {
+        flow,
+        open_quote,
+        ..YamlScan::default()
+    };
+    let mut value_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if let Some(quote) = scan.open_quote {
+            if b == b'\\' && quote == b'"' {
+                i += 2;
+                continue;
+            }
+            if b == quote {
+                if quote == b'\'' && bytes.get(i + 1) == Some(&b'\'') {
+                    i += 2;
+                    continue;
+                }
+                scan.open_quote = None;
+            }
+            i += 1;
+            continue;
+        }
+        let after_space = i == 0 || bytes[i - 1] == b' ' || bytes[i - 1] == b'\t';
+        match b {
+            b'#' if after_space => {
+                scan.comment = Some(i);
+                break;
+            }
+            b'"' | b'\'' if value_start || scan.flow > 0 => scan.open_quote = Some(b),
+            b'[' | b'{' if value_start || scan.flow > 0 => scan.flow += 1,
+            b']' | b'}' if scan.flow > 0 => scan.flow -= 1,
+            b':' if scan.flow == 0
+                && scan.colon.is_none()
+                && matches!(bytes.get(i + 1), None | Some(b' ') | Some(b'\t')) =>
+            {
+                scan.colon = Some(i);
+                value_start = true;
+                i += 1;
+                continue;
+            }
+            b'-' if value_start && matches!(bytes.get(i + 1), None | Some(b' ')) => {
+                i += 1;
+                continue;
+            }
+            b' ' | b'\t' => {
+                i += 1;
+                continue;
+            }
+            b',' if scan.flow > 0 => {
+                i += 1;
+                value_start = true;
+                continue;
+            }
+            _ => {}
+        }
+        value_start = false;
+        i += 1;
+    }
+    scan
+}
+
+fn is_block_scalar_header(code: &str) -> bool {
+    let code = code.trim_end();
+    let Some(at) = code.rfind(['|', '>']) else {
+        return false;
+    };
+    let before = &code[..at];
+    (before.is_empty() || before.ends_with(' ') || before.ends_with(':') || before.ends_with('-'))
+        && code[at + 1..]
+            .bytes()
+            .all(|b| b == b'-' || b == b'+' || b.is_ascii_digit())
+}
+
+fn shift_line(line: &str, delta: isize) -> String {
+    if delta >= 0 {
+        format!("{}{}", " ".repeat(delta as usize), line)
+    } else {
+        let leading = line.len() - line.trim_start_matches(' ').len();
+        line[leading.min(delta.unsigned_abs())..].to_string()
+    }
+}
+
+struct YamlLevel {
+    indent: usize,
+    columns: Vec<(usize, usize)>,
+}
+
+impl YamlLevel {
+    fn new(line: &str, indent: usize, new_indent: usize) -> Self {
+        let mut columns = vec![(indent, new_indent)];
+        let mut rest = line;
+        while let Some(after) = rest.strip_prefix('-')
+            && after.starts_with(' ')
+        {
+            let (col, new_col) = columns[columns.len() - 1];
+            let next = after.trim_start();
+            columns.push((col + rest.len() - next.len(), new_col + 2));
+            rest = next;
+        }
+        YamlLevel { indent, columns }
+    }
+
+    fn child_indent(&self, indent: usize) -> usize {
+        let (content, new_content) = self.columns[self.columns.len() - 1];
+        match self.columns.iter().find(|(col, _)| *col == indent) {
+            Some((_, new_col)) => *new_col,
+            None if indent > content => new_content + YAML_INDENT,
+            None => new_content,
+        }
+    }
+}
+
+enum YamlContinuation {
+    None,
+    Block {
+        parent: usize,
+        target: usize,
+        delta: Option<isize>,
+    },
+    Flow {
+        flow: isize,
+        quote: Option<u8>,
+        delta: isize,
+    },
+}
+
+fn normalize_yaml_code(code: &str, colon: Option<usize>) -> String {
+    let mut code = code.to_string();
+    if let Some(at) = colon
+        && at + 1 < code.len()
+    {
+        code = format!("{}: {}", &code[..at], code[at + 1..].trim_start());
+    }
+    let mut rest = code.as_str();
+    let mut prefix = String::new();
+    while let Some(after) = rest.strip_prefix('-')
+        && (after.is_empty() || after.starts_with(' '))
+    {
+        prefix.push_str(if after.trim_start().is_empty() {
+            "-"
+        } else {
+            "- "
+        });
+        rest = after.trim_start();
+    }
+    format!("{}{}", prefix, rest)
+}
+
+pub fn format_yaml(input: &str) -> Result<String> {
+    let original = yaml_documents(input).context("Invalid YAML")?;
+    let mut out = String::with_capacity(input.len());
+    let mut levels: Vec<YamlLevel> = Vec::new();
+    let mut continuation = YamlContinuation::None;
+    for line in input.lines() {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let trimmed = line.trim();
+        match continuation {
+            YamlContinuation::Block {
+                parent,
+                target,
+                delta,
+            } => {
+                if trimmed.is_empty() {
+                    if let Some(delta) = delta {
+                        out.push_str(&shift_line(line, delta));
+                    }
+                    out.push('\n');
+                    continue;
+                }
+                if indent > parent {
+                    let delta = delta.unwrap_or(target as isize - indent as isize);
+                    out.push_str(&shift_line(line, delta));
+                    out.push('\n');
+                    continuation = YamlContinuation::Block {
+                        parent,
+                        target,
+                        delta: Some(delta),
+                    };
+                    continue;
+                }
+                continuation = YamlContinuation::None;
+            }
+            YamlContinuation::Flow { flow, quote, delta } => {
+                let scan = scan_yaml_line(line, flow, quote);
+                out.push_str(shift_line(line.trim_end(), delta).as_str());
+                out.push('\n');
+                continuation = if scan.flow > 0 || scan.open_quote.is_some() {
+                    YamlContinuation::Flow {
+                        flow: scan.flow,
+                        quote: scan.open_quote,
+                        delta,
+                    }
+                } else {
+                    YamlContinuation::None
+                };
+                continue;
+            }
+            YamlContinuation::None => {}
+        }
+        if trimmed.is_empty() {
+            push_blank(&mut out);
+            continue;
+        }
+        if indent == 0
+            && (trimmed.starts_with("---")
+                || trimmed.starts_with("...")
+                || trimmed.starts_with('%'))
+        {
+            levels.clear();
+            out.push_str(line.trim_end());
+            out.push('\n');
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            let new_indent = levels
+                .iter()
+                .rev()
+                .find(|level| level.indent <= indent)
+                .map_or(0, |level| level.child_indent(indent));
+            out.push_str(&" ".repeat(new_indent));
+            out.push_str(trimmed);
+            out.push('\n');
+            continue;
+        }
+        while levels.last().is_some_and(|level| level.indent > indent) {
+            levels.pop();
+        }
+        let new_indent = levels.last().map_or(0, |level| level.child_indent(indent));
+        if levels.last().is_some_and(|level| level.indent == indent) {
+            levels.pop();
+        }
+        let delta = new_indent as isize - indent as isize;
+        let scan = scan_yaml_line(trimmed, 0, None);
+        let (code, comment) = match scan.comment {
+            Some(at) => (trimmed[..at].trim_end(), Some(&trimmed[at..])),
+            None => (trimmed, None),
+        };
+        let code = if scan.flow > 0 || scan.open_quote.is_some() {
+            code.to_string()
+        } else {
+            normalize_yaml_code(code, scan.colon)
+        };
+        let level = YamlLevel::new(trimmed, indent, new_indent);
+        let (content, new_content) = level.columns[level.columns.len() - 1];
+        levels.push(level);
+        out.push_str(&" ".repeat(new_indent));
+        out.push_str(&with_comment(&code, comment));
+        out.push('\n');
+        continuation = if scan.flow > 0 || scan.open_quote.is_some() {
+            YamlContinuation::Flow {
+                flow: scan.flow,
+                quote: scan.open_quote,
+                delta,
+            }
+        } else if is_block_scalar_header(&code) {
+            YamlContinuation::Block {
+                parent: content,
+                target: new_content + YAML_INDENT,
+                delta: code
+                    .ends_with(|c: char| c.is_ascii_digit())
+                    .then_some(delta),
+            }
+        } else {
+            YamlContinuation::None
+        };
+    }
+    let output = finish(out);
+    let formatted =
+        yaml_documents(&output).context("Internal YAML formatter produced invalid YAML")?;
+    if formatted != original {
+        anyhow::bail!("Internal YAML formatter would change the document; leaving it untouched");
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats(format: fn(&str) -> Result<String>, input: &str, expected: &str) {
+        let output = format(input).unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(format(&output).unwrap(), output, "not idempotent");
+    }
+
+    #[test]
+    fn json_keeps_comments_and_trailing_commas() {
+        assert_formats(
+            format_json,
+            "{\"a\": 1, // one\n\"b\": [1,2,],\n/* two */\n\"c\": \"x // not, {comment}\",}\n",
+            "{\n  \"a\": 1, // one\n  \"b\": [\n    1,\n    2,\n  ],\n  /* two */\n  \"c\": \"x // not, {comment}\",\n}\n",
+        );
+    }
+
+    #[test]
+    fn toml_hash_inside_strings_is_not_a_comment() {
+        assert_formats(
+            format_toml,
+            "a=\"x # y\"   # real\nb = '''\n# not a comment\n'''\nc = 'z#'\n",
+            "a = \"x # y\" # real\nb = '''\n# not a comment\n'''\nc = 'z#'\n",
+        );
+    }
+
+    #[test]
+    fn yaml_hash_inside_strings_is_not_a_comment() {
+        assert_formats(
+            format_yaml,
+            "a:   \"x # y\" # real\nb: 'it''s # fine'\nc:  x#y\nd:\n    - 1 # one\n",
+            "a: \"x # y\" # real\nb: 'it''s # fine'\nc: x#y\nd:\n  - 1 # one\n",
+        );
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        for input in [
+            "{\"a\": [1}",
+            "{\"a\": \"b}",
+            "[1, 2",
+            "{\"a\" 1 2 3}",
+            "{'a': 1}",
+            "[1,, 2]",
+        ] {
+            assert!(format_json(input).is_err(), "{input:?}");
+        }
+        assert!(format_toml("a = ").is_err());
+        assert!(format_toml("a = 1\na = 2\n").is_err());
+        assert!(format_yaml("a: [1").is_err());
+    }
+}
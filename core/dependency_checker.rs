@@ -1,213 +1,162 @@
+use crate::config::Config;
+use crate::formatters::{FormatterRegistry, FormatterSpec};
+use crate::languages::supported_languages;
 use anyhow::Result;
 use std::collections::HashSet;
-use std::process::Command;
 
-pub struct ToolInfo {
-    pub name: &'static str,
-    pub check_command: &'static [&'static str],
-    pub install_hint: &'static str,
-    pub languages: &'static [&'static str],
-}
-
-const TOOLS: &[ToolInfo] = &[
-    ToolInfo {
-        name: "rustfmt",
-        check_command: &["rustfmt", "--version"],
-        install_hint: "Install rustfmt component (e.g., 'rustup component add rustfmt')",
-        languages: &["rust"],
-    },
-    ToolInfo {
-        name: "astyle",
-        check_command: &["astyle", "--version"],
-        install_hint: "Install astyle (e.g., 'sudo apt install astyle', 'brew install astyle')",
-        languages: &["c", "cpp"],
-    },
-    ToolInfo {
-        name: "rubocop",
-        check_command: &["rubocop", "--version"],
-        install_hint: "Install rubocop (e.g., 'gem install rubocop')",
-        languages: &["ruby"],
-    },
-    ToolInfo {
-        name: "taplo",
-        check_command: &["taplo", "--version"],
-        install_hint: "Install taplo CLI (e.g., 'cargo install taplo-cli')",
-        languages: &["toml"],
-    },
-    ToolInfo {
-        name: "prettierd",
-        check_command: &["prettierd", "--version"],
-        install_hint: "Install prettierd (e.g., 'npm install -g prettier prettierd')",
-        languages: &[
-            "javascript",
-            "typescript",
-            "json",
-            "yaml",
-            "css",
-            "scss",
-            "less",
-            "markdown",
-        ],
-    },
-    ToolInfo {
-        name: "asmfmt",
-        check_command: &["asmfmt", "--version"],
-        install_hint: "Install asmfmt (check project repo)",
-        languages: &["assembly"],
-    },
-    ToolInfo {
-        name: "crystal",
-        check_command: &["crystal", "--version"],
-        install_hint: "Install Crystal (crystal-lang.org)",
-        languages: &["crystal"],
-    },
-    ToolInfo {
-        name: "fish_indent",
-        check_command: &["fish_indent", "--version"],
-        install_hint: "Install fish shell (includes fish_indent)",
-        languages: &["fish"],
-    },
-    ToolInfo {
-        name: "shfmt",
-        check_command: &["shfmt", "--version"],
-        install_hint: "Install shfmt (e.g., 'apt install shfmt', 'brew install shfmt')",
-        languages: &["shell"],
-    },
-    ToolInfo {
-        name: "stylua",
-        check_command: &["stylua", "--version"],
-        install_hint: "Install stylua (e.g., 'cargo install stylua')",
-        languages: &["lua"],
-    },
-    ToolInfo {
-        name: "black",
-        check_command: &["black", "--version"],
-        install_hint: "Install black (e.g., 'pip install black')",
-        languages: &["python"],
-    },
-    ToolInfo {
-        name: "perltidy",
-        check_command: &["perltidy", "--version"],
-        install_hint: "Install perltidy (e.g., 'cpanm Perl::Tidy', package manager)",
-        languages: &["perl"],
-    },
-    ToolInfo {
-        name: "gofmt",
-        check_command: &["gofmt", "-h"],
-        install_hint: "Install Go (includes gofmt): https://golang.org/doc/install",
-        languages: &["go"],
-    },
-    ToolInfo {
-        name: "elm-format",
-        check_command: &["elm-format", "--help"],
-        install_hint: "Install elm-format (e.g., 'npm install -g elm-format')",
-        languages: &["elm"],
-    },
-    ToolInfo {
-        name: "ormolu",
-        check_command: &["ormolu", "--version"],
-        install_hint: "Install ormolu (e.g., 'cabal install ormolu')",
-        languages: &["haskell"],
-    },
-    ToolInfo {
-        name: "cabal-fmt",
-        check_command: &["cabal-fmt", "--version"],
-        install_hint: "Install cabal-fmt (e.g., 'cabal install cabal-fmt')",
-        languages: &["cabal"],
-    },
-    ToolInfo {
-        name: "tidy",
-        check_command: &["tidy", "-v"],
-        install_hint: "Install tidy-html5 (e.g., 'apt install tidy', 'brew install tidy-html5')",
-        languages: &["html", "xml"],
-    },
-    ToolInfo {
-        name: "nginxfmt",
-        check_command: &["nginxfmt", "--version"],
-        install_hint: "Install nginxfmt (check project repo)",
-        languages: &["conf"],
-    },
-    ToolInfo {
-        name: "nixfmt",
-        check_command: &["nixfmt", "--version"],
-        install_hint: "Install nixfmt (check project repo or Nix packages)",
-        languages: &["nix"],
-    },
-    ToolInfo {
-        name: "ktlint",
-        check_command: &["ktlint", "--version"],
-        install_hint: "Install ktlint (check project repo)",
-        languages: &["kotlin"],
-    },
-    ToolInfo {
-        name: "google-java-format",
-        check_command: &["google-java-format", "--version"],
-        install_hint: "Install google-java-format (download JAR or build plugins)",
-        languages: &["java"],
-    },
-    ToolInfo {
-        name: "swift-format",
-        check_command: &["swift-format", "--version"],
-        install_hint: "Install swift-format (check Swift toolchain/GitHub)",
-        languages: &["swift"],
-    },
-    ToolInfo {
-        name: "dockfmt",
-        check_command: &["dockfmt", "--version"],
-        install_hint: "Install dockfmt (check project repo)",
-        languages: &["dockerfile"],
-    },
-    ToolInfo {
-        name: "djlint",
-        check_command: &["djlint", "--version"],
-        install_hint: "Install djlint (e.g., 'pip install djlint')",
-        languages: &["twig"],
-    },
+const INSTALL_HINTS: &[(&str, &str)] = &[
+    (
+        "rustfmt",
+        "Install rustfmt component (e.g., 'rustup component add rustfmt')",
+    ),
+    (
+        "astyle",
+        "Install astyle (e.g., 'sudo apt install astyle', 'brew install astyle')",
+    ),
+    ("rubocop", "Install rubocop (e.g., 'gem install rubocop')"),
+    (
+        "taplo",
+        "Install taplo CLI (e.g., 'cargo install taplo-cli')",
+    ),
+    (
+        "prettierd",
+        "Install prettierd (e.g., 'npm install -g prettier prettierd')",
+    ),
+    ("asmfmt", "Install asmfmt (check project repo)"),
+    ("crystal", "Install Crystal (crystal-lang.org)"),
+    ("fish_indent", "Install fish shell (includes fish_indent)"),
+    (
+        "shfmt",
+        "Install shfmt (e.g., 'apt install shfmt', 'brew install shfmt')",
+    ),
+    ("stylua", "Install stylua (e.g., 'cargo install stylua')"),
+    ("black", "Install black (e.g., 'pip install black')"),
+    (
+        "perltidy",
+        "Install perltidy (e.g., 'cpanm Perl::Tidy', package manager)",
+    ),
+    (
+        "gofmt",
+        "Install Go (includes gofmt): https://golang.org/doc/install",
+    ),
+    (
+        "elm-format",
+        "Install elm-format (e.g., 'npm install -g elm-format')",
+    ),
+    ("ormolu", "Install ormolu (e.g., 'cabal install ormolu')"),
+    (
+        "cabal-fmt",
+        "Install cabal-fmt (e.g., 'cabal install cabal-fmt')",
+    ),
+    (
+        "tidy",
+        "Install tidy-html5 (e.g., 'apt install tidy', 'brew install tidy-html5')",
+    ),
+    ("nginxfmt", "Install nginxfmt (check project repo)"),
+    (
+        "nixfmt",
+        "Install nixfmt (check project repo or Nix packages)",
+    ),
+    ("ktlint", "Install ktlint (check project repo)"),
+    (
+        "google-java-format",
+        "Install google-java-format (download JAR or build plugins)",
+    ),
+    (
+        "swift-format",
+        "Install swift-format (check Swift toolchain/GitHub)",
+    ),
+    ("dockfmt", "Install dockfmt (check project repo)"),
+    ("djlint", "Install djlint (e.g., 'pip install djlint')"),
+    ("just", "Install just (e.g., 'cargo install just')"),
+    (
+        "clang-format",
+        "Install clang-format (e.g., 'apt install clang-format', 'brew install clang-format')",
+    ),
+    (
+        "prettier",
+        "Install prettier (e.g., 'npm install -g prettier')",
+    ),
+    (
+        "biome",
+        "Install biome (e.g., 'npm install -g @biomejs/biome')",
+    ),
+    ("ruff", "Install ruff (e.g., 'pip install ruff')"),
+    ("isort", "Install isort (e.g., 'pip install isort')"),
+    (
+        "goimports",
+        "Install goimports (e.g., 'go install golang.org/x/tools/cmd/goimports@latest')",
+    ),
+    (
+        "alejandra",
+        "Install alejandra (e.g., 'nix profile install nixpkgs#alejandra')",
+    ),
 ];
 
-fn check_tool_command(command_parts: &[&str]) -> bool {
-    if command_parts.is_empty() {
-        return false;
+fn install_hint(spec: &FormatterSpec) -> &'static str {
+    INSTALL_HINTS
+        .iter()
+        .find(|(name, _)| *name == spec.name || *name == spec.command)
+        .map_or(
+            "Install it or configure another formatter in xzenfmt.toml",
+            |(_, hint)| hint,
+        )
+}
+
+fn check_step(lang: &str, candidates: &[FormatterSpec]) -> (bool, String) {
+    match candidates
+        .iter()
+        .find_map(|spec| Some((spec, spec.version()?)))
+    {
+        Some((spec, version)) if version.is_empty() => {
+            (true, format!("{}: {} OK", lang, spec.name))
+        }
+        Some((spec, version)) => (true, format!("{}: {} OK ({})", lang, spec.name, version)),
+        None => {
+            let tried: Vec<String> = candidates
+                .iter()
+                .map(|spec| format!("{} ({})", spec.name, install_hint(spec)))
+                .collect();
+            (
+                false,
+                format!("{}: Not found. Tried {}", lang, tried.join("; ")),
+            )
+        }
     }
-    Command::new(command_parts[0])
-        .args(&command_parts[1..])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .status()
-        .is_ok_and(|status| status.success())
 }
 
-fn check_single_tool(tool: &ToolInfo) -> (bool, String) {
-    let found = check_tool_command(tool.check_command);
-    let message = if found {
-        format!("{}: OK", tool.name)
-    } else {
-        format!("{}: Not found. {}", tool.name, tool.install_hint)
-    };
-    (found, message)
+fn check_language(registry: &FormatterRegistry, lang: &str) -> Vec<(bool, String)> {
+    registry
+        .steps(lang)
+        .iter()
+        .map(|candidates| check_step(lang, candidates))
+        .collect()
 }
 
-pub fn check_dependencies(filter_langs: &[String]) -> Result<()> {
+pub fn check_dependencies(filter_langs: &[String], config: &Config) -> Result<()> {
     println!("Checking dependencies:");
-    let mut all_ok = true;
-    let mut checked_tools = HashSet::new();
-    let languages_to_filter: HashSet<&str> = filter_langs.iter().map(|s| s.as_str()).collect();
-    let check_all = languages_to_filter.is_empty();
-    let mut tools_needing_check = Vec::new();
+    let registry = FormatterRegistry::new(config);
+    let mut languages: Vec<String> = if filter_langs.is_empty() {
+        supported_languages(config)
+    } else {
+        filter_langs.to_vec()
+    };
+    languages.sort();
+    let mut seen = HashSet::new();
+    languages.retain(|lang| seen.insert(lang.clone()));
 
-    for tool in TOOLS {
-        let lang_match = tool
-            .languages
-            .iter()
-            .any(|lang| languages_to_filter.contains(lang));
-        let tool_is_relevant = check_all || lang_match;
-        if tool_is_relevant && checked_tools.insert(tool.name) {
-            tools_needing_check.push(tool);
+    let mut all_ok = true;
+    let mut checked_any = false;
+    for lang in &languages {
+        for (found, message) in check_language(&registry, lang) {
+            checked_any = true;
+            println!("  {}", message);
+            all_ok &= found;
         }
     }
 
-    if tools_needing_check.is_empty() {
-        if !check_all {
+    if !checked_any {
+        if !filter_langs.is_empty() {
             println!(
                 "No specific formatter dependencies found for language(s): {:?}",
                 filter_langs
@@ -218,14 +167,6 @@ pub fn check_dependencies(filter_langs: &[String]) -> Result<()> {
         return Ok(());
     }
 
-    for tool in tools_needing_check {
-        let (found, message) = check_single_tool(tool);
-        println!("  {}", message);
-        if !found {
-            all_ok = false;
-        }
-    }
-
     if !all_ok {
         anyhow::bail!("One or more required formatters are missing.");
     } else {
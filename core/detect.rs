@@ -0,0 +1,144 @@
+use crate::config::Config;
+use crate::languages;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SNIFF_BYTES: u64 = 4096;
+const MODELINE_LINES: usize = 5;
+
+fn language_for_name<'a>(name: &str, config: &'a Config) -> Option<&'a str> {
+    let name = name.trim().to_lowercase();
+    let name = name.strip_suffix("-mode").unwrap_or(&name);
+    if let Some((lang, _)) = config.languages.get_key_value(name) {
+        return Some(lang);
+    }
+    languages::lookup(name).map(|l| l.name)
+}
+
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words
+            .find(|w| !w.starts_with('-') && !w.contains('='))?
+            .rsplit('/')
+            .next()?;
+    }
+    Some(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+}
+
+fn emacs_variables(line: &str) -> Option<&str> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    Some(line[start..end].trim())
+}
+
+fn emacs_mode(line: &str) -> Option<&str> {
+    let vars = emacs_variables(line)?;
+    if !vars.contains(':') {
+        return Some(vars);
+    }
+    vars.split(';').find_map(|var| {
+        let (key, value) = var.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case("mode")
+            .then(|| value.trim())
+    })
+}
+
+fn vim_options(line: &str) -> Option<&str> {
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .flat_map(|marker| line.match_indices(marker))
+        .filter(|(at, _)| {
+            line[..*at]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+        })
+        .map(|(at, marker)| at + marker.len())
+        .min()?;
+    Some(&line[start..])
+}
+
+fn vim_filetype(line: &str) -> Option<&str> {
+    vim_options(line)?
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syntax" | "syn").then_some(value)
+        })
+}
+
+pub fn is_modeline(comment: &str) -> bool {
+    emacs_variables(comment).is_some() || vim_options(comment).is_some()
+}
+
+fn signature(head: &str) -> Option<&'static str> {
+    let start = head.trim_start_matches('\u{feff}').trim_start();
+    if start.starts_with("<?xml") {
+        return Some("xml");
+    }
+    if start.starts_with("%YAML") {
+        return Some("yaml");
+    }
+    let lower: String = start.chars().take(14).collect::<String>().to_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return Some("html");
+    }
+    None
+}
+
+fn detect<'a>(head: &str, tail: &str, config: &'a Config) -> Option<&'a str> {
+    let mut lines = head.lines();
+    let first = lines.next().unwrap_or_default();
+    if let Some(interpreter) = shebang_interpreter(first) {
+        if let Some((lang, _)) = config.languages.get_key_value(interpreter) {
+            return Some(lang);
+        }
+        if let Some(l) = languages::for_interpreter(interpreter) {
+            return Some(l.name);
+        }
+    }
+    let emacs_line = if first.starts_with("#!") {
+        lines.next().unwrap_or_default()
+    } else {
+        first
+    };
+    if let Some(lang) = emacs_mode(emacs_line).and_then(|mode| language_for_name(mode, config)) {
+        return Some(lang);
+    }
+    let last_lines = tail.lines().rev().take(MODELINE_LINES);
+    if let Some(lang) = head
+        .lines()
+        .take(MODELINE_LINES)
+        .chain(last_lines)
+        .find_map(vim_filetype)
+        .and_then(|ft| language_for_name(ft, config))
+    {
+        return Some(lang);
+    }
+    signature(head)
+}
+
+pub fn language_from_content<'a>(content: &str, config: &'a Config) -> Option<&'a str> {
+    detect(content, content, config)
+}
+
+pub fn language_from_file<'a>(path: &Path, config: &'a Config) -> Option<&'a str> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let mut head = Vec::new();
+    (&mut file).take(SNIFF_BYTES).read_to_end(&mut head).ok()?;
+    if head.contains(&0) {
+        return None;
+    }
+    let mut tail = Vec::new();
+    if len > SNIFF_BYTES {
+        file.seek(SeekFrom::End(-(SNIFF_BYTES as i64))).ok()?;
+        file.take(SNIFF_BYTES).read_to_end(&mut tail).ok()?;
+    }
+    let tail = String::from_utf8_lossy(if tail.is_empty() { &head } else { &tail });
+    detect(&String::from_utf8_lossy(&head), &tail, config)
+}
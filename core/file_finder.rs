@@ -1,9 +1,19 @@
+use crate::backup::STATE_DIR_NAME;
+use crate::comment_index::{CommentKind, CommentsFormat};
+use crate::comment_stats::StatsFormat;
+use crate::config::{Config, ConfigResolver, dir_is_skipped};
+use crate::git::{self, GitArgs};
+use crate::languages::{self, LanguagesFormat, language_for_path, supported_languages};
+use crate::line_ranges::LineRange;
+use crate::processor::CommentStripMode;
+use crate::report::OutputFormat;
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use ignore::overrides::OverrideBuilder;
-use ignore::{DirEntry, WalkBuilder};
+use ignore::WalkBuilder;
+use ignore::overrides::{Override, OverrideBuilder};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Parser, Clone)]
 #[clap(
@@ -25,11 +35,20 @@ pub struct XzenfmtArgs {
 
     #[clap(
         long = "strip-comments",
-        help = "Strip comments only (removes comment-only lines)",
+        help = "Strip comments only (see --comments for which comments are removed)",
         group = "mode"
     )]
     pub strip_comments: bool,
 
+    #[clap(
+        long = "comments",
+        value_enum,
+        value_name = "MODE",
+        conflicts_with_all = ["code_format", "strip_whitespace", "strip_newlines", "strip_dead_code_comments", "strip_redundant"],
+        help = "Which comments to strip: full-line, trailing or all [default: all; implies --strip-comments unless --all is given]"
+    )]
+    pub comments: Option<CommentStripMode>,
+
     #[clap(
         long = "strip-whitespace",
         help = "Strip trailing whitespace only",
@@ -44,6 +63,47 @@ pub struct XzenfmtArgs {
     )]
     pub strip_newlines: bool,
 
+    #[clap(
+        long = "strip-dead-code-comments",
+        help = "Strip only comments that look like commented-out code, keep prose",
+        group = "mode",
+        group = "heuristic"
+    )]
+    pub strip_dead_code_comments: bool,
+
+    #[clap(
+        long = "strip-redundant",
+        help = "Strip only comments that restate the code they describe, keep comments explaining why",
+        group = "mode",
+        group = "heuristic"
+    )]
+    pub strip_redundant: bool,
+
+    #[clap(
+        long = "explain",
+        help = "Print the heuristic score of every comment (with --strip-dead-code-comments or --strip-redundant)",
+        requires = "heuristic"
+    )]
+    pub explain: bool,
+    #[clap(
+        long,
+        help = "Write stripped files without checking that only comments and whitespace were removed"
+    )]
+    pub no_strip_validation: bool,
+    #[clap(
+        long,
+        help = "Format changed files a second time and fail if the formatter changes them again"
+    )]
+    pub verify: bool,
+    #[clap(
+        long,
+        value_name = "START:END",
+        conflicts_with = "changed_lines",
+        help = "Only strip, clean up or format these lines (1-based, inclusive) [multiple allowed]",
+        action = clap::ArgAction::Append
+    )]
+    pub lines: Vec<LineRange>,
+
     #[clap(
         long = "all",
         help = "Run: Format -> Strip Comments -> Format",
@@ -53,14 +113,52 @@ pub struct XzenfmtArgs {
 
     #[clap( long, value_name = "LANG", help = "Restrict to specific languages [multiple allowed]", action = clap::ArgAction::Append )]
     pub lang: Vec<String>,
+    #[clap(
+        long,
+        help = "Read the buffer from stdin and write the result to stdout",
+        requires = "stdin_filepath"
+    )]
+    pub stdin: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Path of the buffer read with --stdin, used for language detection and formatter config discovery",
+        requires = "stdin"
+    )]
+    pub stdin_filepath: Option<PathBuf>,
     #[clap(long, help = "Skip the confirmation prompt")]
     pub no_confirm: bool,
+    #[clap(
+        long,
+        help = "Back up original contents to .xzenfmt/backups so the run can be undone"
+    )]
+    pub backup: bool,
+    #[clap(
+        long,
+        help = "Do not skip files recorded as already clean in .xzenfmt/cache"
+    )]
+    pub no_cache: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Report format; machine-readable formats are printed to stdout instead of the text summary"
+    )]
+    pub output_format: OutputFormat,
     #[clap(long, help = "Check if required external tools are installed")]
     pub check_dependencies: bool,
-    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files to include [multiple allowed]", action = clap::ArgAction::Append )]
+    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files to include, replacing the configured ones [multiple allowed]", action = clap::ArgAction::Append )]
     pub include: Vec<String>,
-    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files/directories to exclude [multiple allowed]", action = clap::ArgAction::Append )]
+    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files/directories to exclude, added to the configured ones [multiple allowed]", action = clap::ArgAction::Append )]
     pub exclude: Vec<String>,
+    #[clap(flatten)]
+    pub git: GitArgs,
+    #[clap(
+        long,
+        conflicts_with = "stdin",
+        help = "Process the staged content of each file and re-stage the result, keeping unstaged changes (implies --staged)"
+    )]
+    pub index: bool,
 }
 
 impl Default for XzenfmtArgs {
@@ -69,14 +167,28 @@ impl Default for XzenfmtArgs {
             path: PathBuf::from("."),
             code_format: true,
             strip_comments: false,
+            comments: None,
             strip_whitespace: false,
             strip_newlines: false,
+            strip_dead_code_comments: false,
+            strip_redundant: false,
+            explain: false,
+            no_strip_validation: false,
+            verify: false,
+            lines: Vec::new(),
             all: false,
             lang: Vec::new(),
+            stdin: false,
+            stdin_filepath: None,
             no_confirm: false,
+            backup: false,
+            no_cache: false,
+            output_format: OutputFormat::Text,
             check_dependencies: false,
             include: Vec::new(),
             exclude: Vec::new(),
+            git: GitArgs::default(),
+            index: false,
         }
     }
 }
@@ -85,6 +197,193 @@ impl Default for XzenfmtArgs {
 pub enum Command {
     #[clap(about = "Generate shell completion scripts")]
     Completion(CompletionArgs),
+    #[clap(about = "List, search and export comments without modifying files")]
+    Comments(CommentsArgs),
+    #[clap(about = "Report comment statistics and density per file, language and directory")]
+    Stats(StatsArgs),
+    #[clap(about = "Inspect the effective xzenfmt.toml configuration")]
+    Config(ConfigArgs),
+    #[clap(about = "Restore the files changed by a backed-up run (the latest by default)")]
+    Undo(UndoArgs),
+    #[clap(about = "List backed-up runs that can be undone")]
+    History(HistoryArgs),
+    #[clap(about = "Manage the cache of already-clean files")]
+    Cache(CacheArgs),
+    #[clap(about = "Install or remove the git pre-commit hook")]
+    Hook(HookArgs),
+    #[clap(about = "List supported languages, how files are matched and their formatters")]
+    Languages(LanguagesArgs),
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct SelectionArgs {
+    #[clap(help = "Path to the file or directory to scan", default_value = ".")]
+    pub path: PathBuf,
+    #[clap( long, value_name = "LANG", help = "Restrict to specific languages [multiple allowed]", action = clap::ArgAction::Append )]
+    pub lang: Vec<String>,
+    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files to include, replacing the configured ones [multiple allowed]", action = clap::ArgAction::Append )]
+    pub include: Vec<String>,
+    #[clap( long, value_name = "PATTERN", help = "Glob pattern for files/directories to exclude, added to the configured ones [multiple allowed]", action = clap::ArgAction::Append )]
+    pub exclude: Vec<String>,
+    #[clap(flatten)]
+    pub git: GitArgs,
+}
+
+impl SelectionArgs {
+    pub fn to_xzenfmt_args(&self) -> XzenfmtArgs {
+        XzenfmtArgs {
+            path: self.path.clone(),
+            lang: self.lang.clone(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            git: self.git.clone(),
+            ..XzenfmtArgs::default()
+        }
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CommentsArgs {
+    #[clap(flatten)]
+    pub selection: SelectionArgs,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Only show comments matching this regular expression"
+    )]
+    pub grep: Option<String>,
+    #[clap( long, value_enum, value_name = "KIND", help = "Only show comments of this kind [multiple allowed]", action = clap::ArgAction::Append )]
+    pub kind: Vec<CommentKind>,
+    #[clap( long, value_name = "TAG", help = "Only show comments tagged like TODO: or FIXME: [multiple allowed]", action = clap::ArgAction::Append )]
+    pub tag: Vec<String>,
+    #[clap(long, value_enum, default_value = "text", help = "Output format")]
+    pub format: CommentsFormat,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct StatsArgs {
+    #[clap(flatten)]
+    pub selection: SelectionArgs,
+    #[clap(long, value_enum, default_value = "table", help = "Output format")]
+    pub format: StatsFormat,
+    #[clap(
+        long,
+        value_name = "RATIO",
+        help = "Exit non-zero if any file's comment-to-code ratio exceeds RATIO (e.g. 0.3)"
+    )]
+    pub threshold: Option<f64>,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ConfigArgs {
+    #[clap(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ConfigAction {
+    #[clap(about = "Print the merged configuration for a path and the file each value came from")]
+    Show(ConfigShowArgs),
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ConfigShowArgs {
+    #[clap(
+        help = "File or directory to resolve the configuration for",
+        default_value = "."
+    )]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct UndoArgs {
+    #[clap(help = "Run id from `xzenfmt history` (defaults to the latest run not yet undone)")]
+    pub run_id: Option<String>,
+    #[clap(
+        long,
+        default_value = ".",
+        help = "Project directory holding the .xzenfmt backups"
+    )]
+    pub path: PathBuf,
+    #[clap(long, help = "Restore even if files were modified after the run")]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct HistoryArgs {
+    #[clap(
+        help = "Project directory holding the .xzenfmt backups",
+        default_value = "."
+    )]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CacheArgs {
+    #[clap(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum CacheAction {
+    #[clap(about = "Delete the .xzenfmt/cache directory")]
+    Clear(CacheClearArgs),
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CacheClearArgs {
+    #[clap(
+        help = "Project directory holding the .xzenfmt cache",
+        default_value = "."
+    )]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct LanguagesArgs {
+    #[clap(
+        help = "Directory whose xzenfmt.toml languages and formatters are included",
+        default_value = "."
+    )]
+    pub path: PathBuf,
+    #[clap(long, value_enum, default_value = "table", help = "Output format")]
+    pub format: LanguagesFormat,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct HookArgs {
+    #[clap(subcommand)]
+    pub action: HookAction,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum HookAction {
+    #[clap(
+        about = "Write a .git/hooks/pre-commit that processes the staged content of staged files"
+    )]
+    Install(HookInstallArgs),
+    #[clap(about = "Remove the pre-commit hook written by `xzenfmt hook install`")]
+    Uninstall(HookUninstallArgs),
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct HookInstallArgs {
+    #[clap(help = "Directory inside the git repository", default_value = ".")]
+    pub path: PathBuf,
+    #[clap(long, help = "Replace an existing pre-commit hook")]
+    pub force: bool,
+    #[clap(
+        last = true,
+        value_name = "ARGS",
+        help = "Extra xzenfmt arguments for the hook, e.g. -- --strip-comments --changed-lines"
+    )]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct HookUninstallArgs {
+    #[clap(help = "Directory inside the git repository", default_value = ".")]
+    pub path: PathBuf,
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -110,100 +409,21 @@ pub struct CliArgs {
     pub main_opts: XzenfmtArgs,
 }
 
-const RUST_EXTENSIONS: &[&str] = &["rs"];
-const C_EXTENSIONS: &[&str] = &["c", "h"];
-const CPP_EXTENSIONS: &[&str] = &["cpp", "cxx", "cc", "hpp"];
-const RUBY_EXTENSIONS: &[&str] = &["rb", "rake"];
-const RUBY_SPECIAL_FILES: &[&str] = &["Rakefile", "Gemfile"];
-const TOML_EXTENSIONS: &[&str] = &["toml"];
-const JSON_EXTENSIONS: &[&str] = &["json", "jsonc"];
-const YAML_EXTENSIONS: &[&str] = &["yaml", "yml"];
-const PYTHON_EXTENSIONS: &[&str] = &["py"];
-const GO_EXTENSIONS: &[&str] = &["go"];
-const LUA_EXTENSIONS: &[&str] = &["lua"];
-const SHELL_EXTENSIONS: &[&str] = &["sh", "bash"];
-const FISH_EXTENSIONS: &[&str] = &["fish"];
-const PERL_EXTENSIONS: &[&str] = &["pl", "pm"];
-const HASKELL_EXTENSIONS: &[&str] = &["hs", "lhs"];
-const CABAL_EXTENSIONS: &[&str] = &["cabal"];
-const ELM_EXTENSIONS: &[&str] = &["elm"];
-const CRYSTAL_EXTENSIONS: &[&str] = &["cr"];
-const JAVA_EXTENSIONS: &[&str] = &["java"];
-const KOTLIN_EXTENSIONS: &[&str] = &["kt", "kts"];
-const SWIFT_EXTENSIONS: &[&str] = &["swift"];
-const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown"];
-const HTML_EXTENSIONS: &[&str] = &["html", "htm"];
-const XML_EXTENSIONS: &[&str] = &["xml", "xhtml"];
-const CSS_EXTENSIONS: &[&str] = &["css"];
-const SCSS_EXTENSIONS: &[&str] = &["scss"];
-const LESS_EXTENSIONS: &[&str] = &["less"];
-const NIX_EXTENSIONS: &[&str] = &["nix"];
-const TWIG_EXTENSIONS: &[&str] = &["twig"];
-const DOCKERFILE_FILENAMES: &[&str] = &["Dockerfile"];
-const CONF_EXTENSIONS: &[&str] = &["conf"];
-const ASSEMBLY_EXTENSIONS: &[&str] = &["asm", "s"];
-
-fn build_language_extension_map() -> HashMap<&'static str, &'static [&'static str]> {
-    let mut m = HashMap::new();
-    m.insert("rust", RUST_EXTENSIONS);
-    m.insert("c", C_EXTENSIONS);
-    m.insert("cpp", CPP_EXTENSIONS);
-    m.insert("ruby", RUBY_EXTENSIONS);
-    m.insert("toml", TOML_EXTENSIONS);
-    m.insert("json", JSON_EXTENSIONS);
-    m.insert("yaml", YAML_EXTENSIONS);
-    m.insert("python", PYTHON_EXTENSIONS);
-    m.insert("go", GO_EXTENSIONS);
-    m.insert("lua", LUA_EXTENSIONS);
-    m.insert("shell", SHELL_EXTENSIONS);
-    m.insert("fish", FISH_EXTENSIONS);
-    m.insert("perl", PERL_EXTENSIONS);
-    m.insert("haskell", HASKELL_EXTENSIONS);
-    m.insert("cabal", CABAL_EXTENSIONS);
-    m.insert("elm", ELM_EXTENSIONS);
-    m.insert("crystal", CRYSTAL_EXTENSIONS);
-    m.insert("java", JAVA_EXTENSIONS);
-    m.insert("kotlin", KOTLIN_EXTENSIONS);
-    m.insert("swift", SWIFT_EXTENSIONS);
-    m.insert("markdown", MARKDOWN_EXTENSIONS);
-    m.insert("html", HTML_EXTENSIONS);
-    m.insert("xml", XML_EXTENSIONS);
-    m.insert("css", CSS_EXTENSIONS);
-    m.insert("scss", SCSS_EXTENSIONS);
-    m.insert("less", LESS_EXTENSIONS);
-    m.insert("nix", NIX_EXTENSIONS);
-    m.insert("twig", TWIG_EXTENSIONS);
-    m.insert("conf", CONF_EXTENSIONS);
-    m.insert("assembly", ASSEMBLY_EXTENSIONS);
-    m
-}
-fn build_special_filename_map() -> HashMap<&'static str, &'static str> {
-    let mut m = HashMap::new();
-    for &f in RUBY_SPECIAL_FILES {
-        m.insert(f, "ruby");
-    }
-    for &f in DOCKERFILE_FILENAMES {
-        m.insert(f, "dockerfile");
-    }
-    m
-}
-
-pub fn find_files(args: &XzenfmtArgs) -> Result<Vec<PathBuf>> {
+pub fn find_files(args: &XzenfmtArgs, config: &Config) -> Result<Vec<PathBuf>> {
     let r = &args.path;
     if !r.exists() {
         anyhow::bail!("Path not found: {}", r.display());
     }
-    let l = build_language_extension_map();
-    let s = build_special_filename_map();
-    let mut k: HashSet<&str> = l.keys().cloned().collect();
-    k.insert("dockerfile");
+    let k = supported_languages(config);
     let t: Vec<String> = if args.lang.is_empty() {
-        k.iter().map(|&s_val| s_val.to_string()).collect()
+        k.clone()
     } else {
         let mut v = Vec::new();
         for i in &args.lang {
-            if k.contains(i.as_str()) {
+            if k.contains(i) {
                 v.push(i.clone());
+            } else if let Some(l) = languages::lookup(i) {
+                v.push(l.name.to_string());
             } else {
                 eprintln!("Warning: Unsupported language specified, skipping: {}", i);
             }
@@ -217,21 +437,70 @@ pub fn find_files(args: &XzenfmtArgs) -> Result<Vec<PathBuf>> {
     w.standard_filters(true);
     w.hidden(false);
     let mut o = OverrideBuilder::new(r);
-    for p in &args.exclude {
+    let include = if args.include.is_empty() {
+        &config.include
+    } else {
+        &args.include
+    };
+    for p in include {
+        o.add(p).with_context(|| format!("Include: {}", p))?;
+    }
+    for p in config.exclude.iter().chain(&args.exclude) {
         let q = format!("!{}", p);
         o.add(&q).with_context(|| format!("Exclude: {}", p))?;
     }
-    for p in &args.include {
-        o.add(p).with_context(|| format!("Include: {}", p))?;
-    }
     let v = o.build().context("Overrides")?;
+    let ts: HashSet<_> = t.iter().map(String::as_str).collect();
+    let mut resolver = ConfigResolver::new(config);
+    let mut nested = NestedPatterns {
+        base: config,
+        cli_include: !args.include.is_empty(),
+        matchers: HashMap::new(),
+    };
+    if args.git.is_active() {
+        let mut f = Vec::new();
+        for p in git::selected_files(r, &args.git)? {
+            let hidden_state = p.components().any(|c| c.as_os_str() == STATE_DIR_NAME);
+            if !p.is_file() || hidden_state || v.matched(&p, false).is_ignore() {
+                continue;
+            }
+            let c = resolver.resolve(&p)?;
+            if !c.is_skipped() && nested.allows(&p, &c)? && is_target_entry(&p, &ts, &c) {
+                f.push(p);
+            }
+        }
+        return Ok(f);
+    }
     w.overrides(v);
+    let config_error: Arc<Mutex<Option<anyhow::Error>>> = Arc::default();
+    let filter_error = Arc::clone(&config_error);
+    w.filter_entry(move |e| {
+        let is_dir = e.file_type().is_some_and(|ft| ft.is_dir());
+        if e.depth() == 0 || !is_dir {
+            return true;
+        }
+        if e.file_name() == STATE_DIR_NAME || e.file_name() == ".git" {
+            return false;
+        }
+        match dir_is_skipped(e.path()) {
+            Ok(skipped) => !skipped,
+            Err(err) => {
+                filter_error.lock().unwrap().get_or_insert(err);
+                false
+            }
+        }
+    });
     let mut f = Vec::new();
-    let ts: HashSet<_> = t.iter().map(String::as_str).collect();
     for i in w.build() {
         match i {
             Ok(e) => {
-                if e.file_type().is_some_and(|ft| ft.is_file()) && is_target_entry(&e, &ts, &l, &s)
+                if !e.file_type().is_some_and(|ft| ft.is_file()) {
+                    continue;
+                }
+                let c = resolver.resolve(e.path())?;
+                if !c.is_skipped()
+                    && nested.allows(e.path(), &c)?
+                    && is_target_entry(e.path(), &ts, &c)
                 {
                     f.push(e.into_path());
                 }
@@ -241,35 +510,136 @@ pub fn find_files(args: &XzenfmtArgs) -> Result<Vec<PathBuf>> {
             }
         }
     }
+    if let Some(err) = config_error.lock().unwrap().take() {
+        return Err(err);
+    }
     if f.is_empty() && !t.is_empty() {
-        println!("No files found for: {:?}", t);
+        eprintln!("No files found for: {:?}", t);
     }
     f.sort();
     Ok(f)
 }
-fn is_target_entry(
-    e: &DirEntry,
-    t: &HashSet<&str>,
-    l: &HashMap<&'static str, &'static [&'static str]>,
-    s: &HashMap<&'static str, &'static str>,
-) -> bool {
-    let p = e.path();
-    let n = match p.file_name() {
-        Some(name_val) => name_val,
-        None => return false,
-    };
-    let ns = n.to_string_lossy();
-    let x = p.extension();
-    if let Some(g) = s.get(ns.as_ref()) {
-        return t.is_empty() || t.contains(g);
+fn is_target_entry(p: &Path, t: &HashSet<&str>, c: &Config) -> bool {
+    language_for_path(p, c).is_some_and(|g| t.is_empty() || t.contains(g))
+}
+
+struct NestedPatterns<'a> {
+    base: &'a Config,
+    cli_include: bool,
+    matchers: HashMap<(PathBuf, bool), Override>,
+}
+
+impl NestedPatterns<'_> {
+    fn matcher(&mut self, source: &Path, patterns: &[String], exclude: bool) -> Result<&Override> {
+        let key = (source.to_path_buf(), exclude);
+        if !self.matchers.contains_key(&key) {
+            let mut o = OverrideBuilder::new(source.parent().unwrap_or(Path::new("/")));
+            for p in patterns {
+                let glob = if exclude {
+                    format!("!{}", p)
+                } else {
+                    p.clone()
+                };
+                o.add(&glob)
+                    .with_context(|| format!("{}: {}", source.display(), p))?;
+            }
+            self.matchers
+                .insert(key.clone(), o.build().context("Overrides")?);
+        }
+        Ok(&self.matchers[&key])
     }
-    if let Some(o_val) = x {
-        let xs = o_val.to_string_lossy().to_lowercase();
-        for (g, e_val) in l {
-            if e_val.contains(&xs.as_ref()) {
-                return t.is_empty() || t.contains(g);
+
+    fn allows(&mut self, path: &Path, c: &Config) -> Result<bool> {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let nested = |key: &str| {
+            c.origins
+                .get(key)
+                .filter(|source| self.base.origins.get(key) != Some(*source))
+                .cloned()
+        };
+        if let Some(source) = nested("include").filter(|_| !self.cli_include)
+            && !self
+                .matcher(&source, &c.include, false)?
+                .matched(&path, false)
+                .is_whitelist()
+        {
+            return Ok(false);
+        }
+        if let Some(source) = nested("exclude") {
+            let root = source.parent().unwrap_or(Path::new("/"));
+            let o = self.matcher(&source, &c.exclude, true)?;
+            if o.matched(&path, false).is_ignore()
+                || path
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|dir| dir.starts_with(root) && *dir != root)
+                    .any(|dir| o.matched(dir, true).is_ignore())
+            {
+                return Ok(false);
             }
         }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn found(root: &Path) -> Vec<String> {
+        let args = CliArgs::parse_from(["xzenfmt".as_ref(), root.as_os_str()]).main_opts;
+        find_files(&args, &crate::config::load_config(root).unwrap())
+            .unwrap()
+            .iter()
+            .map(|p| p.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn only_extensionless_files_are_sniffed() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("icon.svg", "<?xml version=\"1.0\"?>\n<svg/>\n"),
+            ("notes.txt", "# vim: ft=python\n"),
+            ("data.csv", "#!/bin/sh\n"),
+            ("run", "#!/bin/sh\necho hi\n"),
+            ("build.sh.in", "#!/bin/sh\necho @VERSION@\n"),
+        ];
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        assert_eq!(found(dir.path()), ["build.sh.in", "run"]);
+    }
+
+    #[test]
+    fn nested_configs_filter_their_own_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("a.rs", ""),
+            ("a.py", ""),
+            ("sub/xzenfmt.toml", "exclude = [\"generated\"]\n"),
+            ("sub/b.rs", ""),
+            ("sub/generated/x.rs", ""),
+            ("sub/only/xzenfmt.toml", "include = [\"*.rs\"]\n"),
+            ("sub/only/c.rs", ""),
+            ("sub/only/d.py", ""),
+            ("sub/only/generated/y.rs", ""),
+        ];
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        assert_eq!(
+            found(dir.path()),
+            [
+                "a.py",
+                "a.rs",
+                "sub/b.rs",
+                "sub/only/c.rs",
+                "sub/xzenfmt.toml"
+            ]
+        );
     }
-    false
 }
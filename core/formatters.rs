@@ -0,0 +1,635 @@
+use crate::command_runner::{
+    ToolError, run_formatter, run_formatter_batch, run_formatter_stdin_stdout, tool_version,
+};
+use crate::config::Config;
+use crate::data_formats::InternalFormatter;
+use crate::languages;
+use crate::line_ranges::{self, LineIndex, LineRange};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+type VersionCache = HashMap<(String, Vec<String>), Option<String>>;
+
+pub const FILE_PLACEHOLDER: &str = "{file}";
+const START_CHAR_PLACEHOLDER: &str = "{start_char}";
+const END_CHAR_PLACEHOLDER: &str = "{end_char}";
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const BATCH_SECS_PER_FILE: u64 = 1;
+const MAX_BATCH_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FormatterSpec {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    pub stdin: bool,
+    pub success_codes: Vec<i32>,
+    pub version_args: Vec<String>,
+    pub timeout_secs: u64,
+    pub batch: usize,
+    pub range_args: Vec<String>,
+    pub internal: Option<InternalFormatter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatterConfig {
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub stdin: Option<bool>,
+    pub success_codes: Option<Vec<i32>>,
+    pub version_args: Option<Vec<String>>,
+    pub timeout: Option<u64>,
+    pub batch: Option<usize>,
+    pub range_args: Option<Vec<String>>,
+}
+
+struct Builtin {
+    name: &'static str,
+    command: &'static str,
+    args: &'static [&'static str],
+    stdin: bool,
+    version_args: &'static [&'static str],
+    timeout_secs: u64,
+    batch: usize,
+    range_args: &'static [&'static str],
+    internal: Option<InternalFormatter>,
+}
+
+const fn builtin(
+    name: &'static str,
+    command: &'static str,
+    args: &'static [&'static str],
+) -> Builtin {
+    Builtin {
+        name,
+        command,
+        args,
+        stdin: false,
+        version_args: &["--version"],
+        timeout_secs: DEFAULT_TIMEOUT_SECS,
+        batch: 0,
+        range_args: &[],
+        internal: None,
+    }
+}
+
+const fn internal(name: &'static str, formatter: InternalFormatter) -> Builtin {
+    Builtin {
+        version_args: &[],
+        internal: Some(formatter),
+        ..builtin(name, name, &[])
+    }
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        batch: 50,
+        ..builtin("rustfmt", "rustfmt", &[])
+    },
+    builtin("astyle", "astyle", &["--style=kr", "-n"]),
+    builtin("astyle-google", "astyle", &["--style=google", "-n"]),
+    Builtin {
+        batch: 100,
+        range_args: &["--lines={start}:{end}"],
+        ..builtin("clang-format", "clang-format", &["-i"])
+    },
+    Builtin {
+        batch: 50,
+        ..builtin("rubocop", "rubocop", &["-A", "--fail-level", "error"])
+    },
+    Builtin {
+        batch: 100,
+        ..builtin("taplo", "taplo", &["fmt"])
+    },
+    builtin("prettierd", "prettierd", &[]),
+    Builtin {
+        batch: 100,
+        range_args: &["--range-start={start_char}", "--range-end={end_char}"],
+        ..builtin("prettier", "prettier", &["--write"])
+    },
+    Builtin {
+        batch: 100,
+        ..builtin("biome", "biome", &["format", "--write"])
+    },
+    builtin("asmfmt", "asmfmt", &[]),
+    Builtin {
+        stdin: true,
+        ..builtin("crystal", "crystal", &["tool", "format", "-"])
+    },
+    builtin("fish_indent", "fish_indent", &["-w"]),
+    Builtin {
+        batch: 100,
+        ..builtin("shfmt", "shfmt", &["-w", "-i", "4", "-ci"])
+    },
+    Builtin {
+        batch: 100,
+        ..builtin("stylua", "stylua", &[])
+    },
+    Builtin {
+        batch: 100,
+        range_args: &["--line-ranges={start}-{end}"],
+        ..builtin("black", "black", &["-q"])
+    },
+    Builtin {
+        batch: 100,
+        ..builtin("ruff", "ruff", &["format", "-q"])
+    },
+    Builtin {
+        batch: 100,
+        ..builtin("isort", "isort", &["-q"])
+    },
+    Builtin {
+        stdin: true,
+        ..builtin("perltidy", "perltidy", &["-st"])
+    },
+    Builtin {
+        version_args: &[],
+        batch: 100,
+        ..builtin("gofmt", "gofmt", &["-w"])
+    },
+    Builtin {
+        version_args: &[],
+        ..builtin("goimports", "goimports", &["-w"])
+    },
+    Builtin {
+        version_args: &["--help"],
+        ..builtin("elm-format", "elm-format", &["--yes"])
+    },
+    builtin("ormolu", "ormolu", &["-m", "inplace"]),
+    builtin("cabal-fmt", "cabal-fmt", &["-i"]),
+    Builtin {
+        version_args: &["-v"],
+        ..builtin("tidy", "tidy", &["-m", "-q", "-indent"])
+    },
+    Builtin {
+        timeout_secs: 120,
+        batch: 50,
+        ..builtin("ktlint", "ktlint", &["-F"])
+    },
+    Builtin {
+        timeout_secs: 120,
+        batch: 50,
+        ..builtin("google-java-format", "google-java-format", &["-i"])
+    },
+    builtin("swift-format", "swift-format", &["format", "--in-place"]),
+    builtin("dockfmt", "dockfmt", &["format", "-w"]),
+    builtin("nginxfmt", "nginxfmt", &[]),
+    builtin("nixfmt", "nixfmt", &[]),
+    builtin("alejandra", "alejandra", &["-q"]),
+    builtin("djlint", "djlint", &["--reformat"]),
+    builtin("just", "just", &["--unstable", "--fmt", "--justfile"]),
+    internal("xzenfmt-json", InternalFormatter::Json),
+    internal("xzenfmt-toml", InternalFormatter::Toml),
+    internal("xzenfmt-yaml", InternalFormatter::Yaml),
+];
+
+pub fn default_formatters(lang: &str) -> &'static [&'static str] {
+    languages::lookup(lang).map_or(&[], |l| l.formatters)
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|s| s.to_string()).collect()
+}
+
+impl FormatterSpec {
+    fn builtin(name: &str) -> Option<Self> {
+        let b = BUILTINS.iter().find(|b| b.name == name)?;
+        Some(FormatterSpec {
+            name: b.name.to_string(),
+            command: b.command.to_string(),
+            args: strings(b.args),
+            stdin: b.stdin,
+            success_codes: vec![0],
+            version_args: strings(b.version_args),
+            timeout_secs: b.timeout_secs,
+            batch: b.batch,
+            range_args: strings(b.range_args),
+            internal: b.internal,
+        })
+    }
+
+    fn command_only(name: &str) -> Self {
+        FormatterSpec {
+            name: name.to_string(),
+            command: name.to_string(),
+            args: Vec::new(),
+            stdin: false,
+            success_codes: vec![0],
+            version_args: strings(&["--version"]),
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            batch: 0,
+            range_args: Vec::new(),
+            internal: None,
+        }
+    }
+
+    fn apply(&mut self, config: &FormatterConfig) {
+        if let Some(command) = &config.command {
+            self.command = command.clone();
+            self.internal = None;
+        }
+        if let Some(args) = &config.args {
+            self.args = args.clone();
+        }
+        if let Some(stdin) = config.stdin {
+            self.stdin = stdin;
+        }
+        if let Some(codes) = &config.success_codes {
+            self.success_codes = codes.clone();
+        }
+        if let Some(version_args) = &config.version_args {
+            self.version_args = version_args.clone();
+        }
+        if let Some(timeout) = config.timeout {
+            self.timeout_secs = timeout;
+        }
+        if let Some(batch) = config.batch {
+            self.batch = batch;
+        }
+        if let Some(range_args) = &config.range_args {
+            self.range_args = range_args.clone();
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout_secs > 0).then(|| Duration::from_secs(self.timeout_secs))
+    }
+
+    /// The single-file timeout plus a second per file, capped at five minutes
+    /// (or the single-file timeout when that is longer).
+    fn batch_timeout(&self, files: usize) -> Option<Duration> {
+        let allowance = BATCH_SECS_PER_FILE.saturating_mul(files as u64);
+        let cap = self.timeout_secs.max(MAX_BATCH_TIMEOUT_SECS);
+        self.timeout()
+            .map(|_| Duration::from_secs(self.timeout_secs.saturating_add(allowance).min(cap)))
+    }
+
+    pub fn expanded_args(&self, file_path: &Path) -> Vec<String> {
+        let file = file_path.to_string_lossy();
+        let mut args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| a.replace(FILE_PLACEHOLDER, &file))
+            .collect();
+        let has_placeholder = self.args.iter().any(|a| a.contains(FILE_PLACEHOLDER));
+        if !self.stdin && !has_placeholder {
+            args.push(file.into_owned());
+        }
+        args
+    }
+
+    pub fn supports_ranges(&self) -> bool {
+        !self.range_args.is_empty() && self.internal.is_none()
+    }
+
+    pub fn expanded_range_args(&self, content: &str, ranges: &[LineRange]) -> Vec<String> {
+        let index = LineIndex::new(content);
+        let last_line = index.line_count();
+        let by_span = self
+            .range_args
+            .iter()
+            .any(|a| a.contains(START_CHAR_PLACEHOLDER) || a.contains(END_CHAR_PLACEHOLDER));
+        if by_span {
+            let Some((start, end)) = index.span(content, ranges) else {
+                return Vec::new();
+            };
+            let start_char = content[..start].chars().count().to_string();
+            let end_char = content[..end].chars().count().to_string();
+            return self
+                .range_args
+                .iter()
+                .map(|a| {
+                    a.replace(START_CHAR_PLACEHOLDER, &start_char)
+                        .replace(END_CHAR_PLACEHOLDER, &end_char)
+                })
+                .collect();
+        }
+        ranges
+            .iter()
+            .filter(|r| r.start <= last_line)
+            .flat_map(|r| {
+                let start = r.start.to_string();
+                let end = r.end.min(last_line).to_string();
+                self.range_args
+                    .iter()
+                    .map(move |a| a.replace("{start}", &start).replace("{end}", &end))
+            })
+            .collect()
+    }
+
+    pub fn batch_args(&self, files: &[&Path]) -> Option<Vec<String>> {
+        if self.batch < 2 || self.stdin || self.internal.is_some() {
+            return None;
+        }
+        let files = files.iter().map(|f| f.to_string_lossy().into_owned());
+        let mut args = Vec::new();
+        let mut placed = false;
+        for arg in &self.args {
+            if arg == FILE_PLACEHOLDER {
+                args.extend(files.clone());
+                placed = true;
+            } else if arg.contains(FILE_PLACEHOLDER) {
+                return None;
+            } else {
+                args.push(arg.clone());
+            }
+        }
+        if !placed {
+            args.extend(files);
+        }
+        Some(args)
+    }
+
+    pub fn run_batch(&self, files: &[&Path]) -> Result<(), ToolError> {
+        let Some(args) = self.batch_args(files) else {
+            return Err(ToolError::Spawn {
+                tool: self.name.clone(),
+                source: std::io::Error::other("formatter does not support batches"),
+            });
+        };
+        let timeout = self.batch_timeout(files.len());
+        run_formatter_batch(&self.command, &args, &self.success_codes, timeout)
+            .map_err(|e| e.with_tool(self.name.clone()))
+    }
+
+    pub fn is_available(&self) -> bool {
+        if self.internal.is_some() {
+            return true;
+        }
+        static CACHE: OnceLock<Mutex<HashMap<String, bool>>> = OnceLock::new();
+        let cache = CACHE.get_or_init(Default::default);
+        if let Some(&found) = cache.lock().unwrap().get(&self.command) {
+            return found;
+        }
+        let found = find_in_path(&self.command).is_some();
+        cache.lock().unwrap().insert(self.command.clone(), found);
+        found
+    }
+
+    pub fn version(&self) -> Option<String> {
+        if self.internal.is_some() {
+            return Some(format!("built in, {}", env!("CARGO_PKG_VERSION")));
+        }
+        if self.version_args.is_empty() {
+            return self.is_available().then(String::new);
+        }
+        tool_version(&self.command, &self.version_args)
+    }
+
+    fn cached_version(&self) -> Option<String> {
+        static CACHE: OnceLock<Mutex<VersionCache>> = OnceLock::new();
+        let cache = CACHE.get_or_init(Default::default);
+        let key = (self.command.clone(), self.version_args.clone());
+        if let Some(version) = cache.lock().unwrap().get(&key) {
+            return version.clone();
+        }
+        let version = self.version();
+        cache.lock().unwrap().insert(key, version.clone());
+        version
+    }
+
+    pub fn run(&self, file_path: &Path, ranges: Option<&[LineRange]>) -> Result<(), ToolError> {
+        let result = if let Some(internal) = self.internal {
+            internal.format_file(file_path)
+        } else if self.command.is_empty() {
+            Err(ToolError::Spawn {
+                tool: self.name.clone(),
+                source: std::io::Error::new(std::io::ErrorKind::NotFound, "no command configured"),
+            })
+        } else {
+            self.run_command(file_path, ranges)
+        };
+        result.map_err(|e| e.with_tool(self.name.clone()))
+    }
+
+    fn run_command(&self, file_path: &Path, ranges: Option<&[LineRange]>) -> Result<(), ToolError> {
+        let mut args = self.expanded_args(file_path);
+        if let Some(ranges) = ranges {
+            let content = std::fs::read_to_string(file_path).map_err(|source| ToolError::Io {
+                tool: self.name.clone(),
+                source,
+            })?;
+            let file = file_path.to_string_lossy();
+            let at = args.iter().position(|a| *a == file).unwrap_or(args.len());
+            let range_args = self.expanded_range_args(&content, ranges);
+            args.splice(at..at, range_args);
+        }
+        if self.stdin {
+            run_formatter_stdin_stdout(
+                &self.command,
+                &args,
+                file_path,
+                &self.success_codes,
+                self.timeout(),
+            )
+        } else {
+            run_formatter(&self.command, &args, &self.success_codes, self.timeout())
+        }
+    }
+}
+
+pub fn find_in_path(command: &str) -> Option<PathBuf> {
+    let candidate = Path::new(command);
+    if candidate.components().count() > 1 {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(command))
+        .find(|p| p.is_file())
+}
+
+pub struct FormatterRegistry<'a> {
+    config: &'a Config,
+}
+
+impl<'a> FormatterRegistry<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        FormatterRegistry { config }
+    }
+
+    pub fn get(&self, name: &str) -> FormatterSpec {
+        let mut spec =
+            FormatterSpec::builtin(name).unwrap_or_else(|| FormatterSpec::command_only(name));
+        if let Some(overrides) = self.config.formatters.get(name) {
+            spec.apply(overrides);
+        }
+        spec
+    }
+
+    pub fn candidates(&self, lang: &str) -> Vec<FormatterSpec> {
+        let lang_config = self.config.language(lang);
+        let names: Vec<String> = match lang_config.and_then(|c| c.formatter.as_ref()) {
+            Some(names) => names.clone(),
+            None => strings(default_formatters(lang)),
+        };
+        // Language-level `args`/`stdin` are written for the primary formatter;
+        // fallbacks only get their own `[formatters.<name>]` settings.
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut spec = self.get(name);
+                if let Some(c) = lang_config.filter(|_| i == 0) {
+                    spec.apply(&FormatterConfig {
+                        args: c.args.clone(),
+                        stdin: c.stdin,
+                        ..FormatterConfig::default()
+                    });
+                }
+                spec
+            })
+            .collect()
+    }
+
+    pub fn batch_formatter(&self, lang: &str) -> Option<FormatterSpec> {
+        match self.steps(lang).as_slice() {
+            [candidates] => candidates
+                .iter()
+                .find(|spec| spec.is_available())
+                .filter(|spec| spec.batch_args(&[]).is_some())
+                .cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn steps(&self, lang: &str) -> Vec<Vec<FormatterSpec>> {
+        match self.config.language(lang).and_then(|c| c.pipeline.as_ref()) {
+            Some(pipeline) => pipeline
+                .iter()
+                .map(|names| names.iter().map(|name| self.get(name)).collect())
+                .collect(),
+            None => {
+                let candidates = self.candidates(lang);
+                if candidates.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![candidates]
+                }
+            }
+        }
+    }
+
+    pub fn fingerprint(&self, lang: &str) -> String {
+        self.steps(lang)
+            .iter()
+            .map(
+                |candidates| match candidates.iter().find(|s| s.is_available()) {
+                    Some(spec) => format!(
+                        "{}:{}:{:?}:{}:{:?}",
+                        spec.name,
+                        spec.command,
+                        spec.args,
+                        spec.stdin,
+                        spec.cached_version()
+                    ),
+                    None => "unavailable".to_string(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    pub fn format_file(
+        &self,
+        file_path: &Path,
+        lang: &str,
+        ranges: Option<&[LineRange]>,
+    ) -> Result<Vec<String>, ToolError> {
+        let steps = self.steps(lang);
+        let total = steps.len();
+        let mut used = Vec::with_capacity(total);
+        let mut ranges = ranges.map(<[LineRange]>::to_vec);
+        for (i, candidates) in steps.iter().enumerate() {
+            let step = if total > 1 {
+                format!(" (step {}/{})", i + 1, total)
+            } else {
+                String::new()
+            };
+            let Some(spec) = candidates.iter().find(|spec| spec.is_available()) else {
+                return Err(ToolError::Unavailable {
+                    lang: format!("{}{}", lang, step),
+                    tried: candidates.iter().map(|s| s.name.clone()).collect(),
+                });
+            };
+            let Some(ranges) = &mut ranges else {
+                spec.run(file_path, None)?;
+                used.push(spec.name.clone());
+                continue;
+            };
+            let spec = candidates
+                .iter()
+                .find(|spec| spec.supports_ranges() && spec.is_available())
+                .ok_or_else(|| ToolError::RangesUnsupported {
+                    lang: format!("{}{}", lang, step),
+                    tool: spec.name.clone(),
+                })?;
+            let read = |path: &Path| {
+                std::fs::read_to_string(path).map_err(|source| ToolError::Io {
+                    tool: spec.name.clone(),
+                    source,
+                })
+            };
+            let before = read(file_path)?;
+            spec.run(file_path, Some(ranges))?;
+            *ranges = line_ranges::remap(&before, &read(file_path)?, ranges);
+            used.push(spec.name.clone());
+        }
+        Ok(used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn pipeline_steps_get_ranges_remapped_by_earlier_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.rs");
+        let log = dir.path().join("ranges");
+        fs::write(&file, "a\nb\nc\n").unwrap();
+        let config: Config = toml::from_str(&format!(
+            r#"
+[languages.rust]
+pipeline = ["prepend", "record"]
+[formatters.prepend]
+command = "sh"
+args = ["-c", "echo $1 >> '{log}'; printf 'x\nx\n' | cat - $2 > $2.new && mv $2.new $2", "sh"]
+range_args = ["{{start}}:{{end}}"]
+[formatters.record]
+command = "sh"
+args = ["-c", "echo $1 >> '{log}'", "sh"]
+range_args = ["{{start}}:{{end}}"]
+"#,
+            log = log.display()
+        ))
+        .unwrap();
+        let registry = FormatterRegistry::new(&config);
+        let ranges = [LineRange { start: 2, end: 2 }];
+        let used = registry.format_file(&file, "rust", Some(&ranges)).unwrap();
+        assert_eq!(used, ["prepend", "record"]);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "2:2\n4:4\n");
+    }
+
+    #[test]
+    fn ranges_without_range_support_are_a_distinct_error() {
+        let config: Config = toml::from_str(
+            "[languages.rust]\nformatter = \"plain\"\n[formatters.plain]\ncommand = \"true\"\n",
+        )
+        .unwrap();
+        let ranges = [LineRange { start: 1, end: 1 }];
+        let err = FormatterRegistry::new(&config)
+            .format_file(Path::new("a.rs"), "rust", Some(&ranges))
+            .unwrap_err();
+        assert!(
+            matches!(&err, ToolError::RangesUnsupported { tool, .. } if tool == "plain"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use crate::line_ranges::{self, LineRange};
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct GitArgs {
+    #[clap(long, help = "Only files staged in the git index")]
+    pub staged: bool,
+    #[clap(
+        long,
+        help = "Only files changed in the working tree or index compared to HEAD"
+    )]
+    pub changed: bool,
+    #[clap(
+        long,
+        value_name = "REV",
+        help = "Only files changed since the merge base of HEAD and REV (e.g. origin/main)"
+    )]
+    pub since: Option<String>,
+    #[clap(long, help = "Only untracked files that are not ignored")]
+    pub untracked: bool,
+    #[clap(
+        long,
+        help = "Only touch lines changed according to git (against HEAD unless --staged or --since is given)"
+    )]
+    pub changed_lines: bool,
+}
+
+impl GitArgs {
+    pub fn is_active(&self) -> bool {
+        self.staged || self.changed || self.since.is_some() || self.untracked || self.changed_lines
+    }
+}
+
+pub(crate) fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    git_with_input(dir, args, None)
+}
+
+fn git_with_input(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input).context("Failed to write to git")?;
+    }
+    let output = child.wait_with_output().context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+fn git_paths(dir: &Path, args: &[&str]) -> Result<Vec<String>> {
+    let stdout = git(dir, args)?;
+    Ok(stdout
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| String::from_utf8_lossy(path).into_owned())
+        .collect())
+}
+
+struct Repo {
+    absolute: PathBuf,
+    dir: PathBuf,
+    toplevel: PathBuf,
+}
+
+impl Repo {
+    fn open(root: &Path) -> Result<Self> {
+        let absolute = root
+            .canonicalize()
+            .with_context(|| format!("Resolve {}", root.display()))?;
+        let dir = if absolute.is_dir() {
+            absolute.clone()
+        } else {
+            absolute.parent().unwrap_or(&absolute).to_path_buf()
+        };
+        let toplevel = git(&dir, &["rev-parse", "--show-toplevel"])?;
+        let toplevel = PathBuf::from(String::from_utf8_lossy(&toplevel).trim());
+        Ok(Repo {
+            absolute,
+            dir,
+            toplevel,
+        })
+    }
+
+    fn diff_bases(&self, args: &GitArgs) -> Result<Vec<String>> {
+        let mut bases = Vec::new();
+        if args.staged {
+            bases.push("--cached".to_string());
+        }
+        let only_lines = args.changed_lines && !args.staged && args.since.is_none();
+        if args.changed || only_lines {
+            bases.push("HEAD".to_string());
+        }
+        if let Some(rev) = &args.since {
+            let base = git(&self.dir, &["merge-base", "HEAD", rev])
+                .with_context(|| format!("No merge base between HEAD and {}", rev))?;
+            bases.push(String::from_utf8_lossy(&base).trim().to_string());
+        }
+        Ok(bases)
+    }
+
+    fn untracked(&self) -> Result<Vec<String>> {
+        git_paths(
+            &self.toplevel,
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )
+    }
+}
+
+const DIFF_ARGS: &[&str] = &[
+    "-c",
+    "core.quotepath=off",
+    "diff",
+    "--no-color",
+    "--no-ext-diff",
+    "--no-renames",
+    "--diff-filter=ACMRT",
+];
+
+pub fn selected_files(root: &Path, args: &GitArgs) -> Result<BTreeSet<PathBuf>> {
+    let repo = Repo::open(root)?;
+    let mut paths = Vec::new();
+    for base in repo.diff_bases(args)? {
+        let diff = [DIFF_ARGS, &["--name-only", "-z", base.as_str()]].concat();
+        paths.extend(git_paths(&repo.dir, &diff)?);
+    }
+    if args.untracked {
+        paths.extend(repo.untracked()?);
+    }
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let relative = repo
+                .toplevel
+                .join(path)
+                .strip_prefix(&repo.absolute)
+                .ok()?
+                .to_path_buf();
+            if relative.as_os_str().is_empty() {
+                Some(root.to_path_buf())
+            } else {
+                Some(root.join(relative))
+            }
+        })
+        .collect())
+}
+
+fn diff_path(line: &str) -> Option<String> {
+    let path = line.strip_prefix("+++ ")?;
+    if path == "/dev/null" {
+        return None;
+    }
+    let Some(quoted) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return path.strip_prefix("b/").map(str::to_string);
+    };
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next()? {
+            't' => unquoted.push('\t'),
+            'n' => unquoted.push('\n'),
+            other => unquoted.push(other),
+        }
+    }
+    unquoted.strip_prefix("b/").map(str::to_string)
+}
+
+fn hunk_range(line: &str) -> Option<LineRange> {
+    let new_side = line
+        .strip_prefix("@@ ")?
+        .split(' ')
+        .nth(1)?
+        .strip_prefix('+')?;
+    let (start, count) = match new_side.split_once(',') {
+        Some((start, count)) => (start.parse::<usize>().ok()?, count.parse::<usize>().ok()?),
+        None => (new_side.parse::<usize>().ok()?, 1),
+    };
+    (count > 0).then(|| LineRange {
+        start,
+        end: start + count - 1,
+    })
+}
+
+pub fn changed_lines(root: &Path, args: &GitArgs) -> Result<HashMap<PathBuf, Vec<LineRange>>> {
+    let repo = Repo::open(root)?;
+    let mut files: HashMap<PathBuf, Vec<LineRange>> = HashMap::new();
+    for base in repo.diff_bases(args)? {
+        let diff = [
+            DIFF_ARGS,
+            &["-U0", "--src-prefix=a/", "--dst-prefix=b/", base.as_str()],
+        ]
+        .concat();
+        let output = git(&repo.dir, &diff)?;
+        let mut current = None;
+        for line in String::from_utf8_lossy(&output).lines() {
+            if line.starts_with("+++ ") {
+                current = diff_path(line).map(|path| repo.toplevel.join(path));
+            } else if let (Some(path), Some(range)) = (&current, hunk_range(line)) {
+                files.entry(path.clone()).or_default().push(range);
+            }
+        }
+    }
+    if args.untracked {
+        for path in repo.untracked()? {
+            files.insert(repo.toplevel.join(path), vec![LineRange::WHOLE_FILE]);
+        }
+    }
+    Ok(files
+        .into_iter()
+        .map(|(path, ranges)| (path, line_ranges::normalize(ranges)))
+        .collect())
+}
+
+pub struct StagedFile {
+    dir: PathBuf,
+    toplevel: PathBuf,
+    relative: String,
+    mode: String,
+    blob: String,
+}
+
+impl StagedFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = path
+            .file_name()
+            .with_context(|| format!("No file name in {}", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let listing = git(
+            &dir,
+            &[
+                "--literal-pathspecs",
+                "ls-files",
+                "--stage",
+                "-z",
+                "--",
+                &name,
+            ],
+        )?;
+        let listing = String::from_utf8_lossy(&listing);
+        let mut entries = listing.split('\0').filter(|entry| !entry.is_empty());
+        let entry = entries
+            .next()
+            .with_context(|| format!("{} is not in the git index", path.display()))?;
+        if entries.next().is_some() {
+            anyhow::bail!("{} has unresolved merge conflicts", path.display());
+        }
+        let (info, _) = entry
+            .split_once('\t')
+            .with_context(|| format!("Unexpected git ls-files output: {}", entry))?;
+        let mut fields = info.split(' ');
+        let (Some(mode), Some(blob)) = (fields.next(), fields.next()) else {
+            anyhow::bail!("Unexpected git ls-files output: {}", entry);
+        };
+        let location = git(&dir, &["rev-parse", "--show-toplevel", "--show-prefix"])?;
+        let location = String::from_utf8_lossy(&location);
+        let mut lines = location.lines();
+        let toplevel = PathBuf::from(lines.next().unwrap_or_default());
+        let prefix = lines.next().unwrap_or_default();
+        Ok(StagedFile {
+            dir,
+            toplevel,
+            relative: format!("{}{}", prefix, name),
+            mode: mode.to_string(),
+            blob: blob.to_string(),
+        })
+    }
+
+    pub fn content(&self) -> Result<Vec<u8>> {
+        git(&self.dir, &["cat-file", "blob", &self.blob])
+    }
+
+    pub fn write_blob(&self, content: &str) -> Result<String> {
+        let blob = git_with_input(
+            &self.dir,
+            &["hash-object", "-w", "--no-filters", "--stdin"],
+            Some(content.as_bytes()),
+        )?;
+        Ok(String::from_utf8_lossy(&blob).trim().to_string())
+    }
+
+    pub fn stage(&self, blob: &str) -> Result<()> {
+        let info = format!("{},{},{}", self.mode, blob, self.relative);
+        git(&self.toplevel, &["update-index", "--cacheinfo", &info])?;
+        Ok(())
+    }
+
+    pub fn worktree_patch(&self, blob: &str) -> Result<Vec<u8>> {
+        let diff = git(
+            &self.dir,
+            &["diff", "--no-color", "--no-ext-diff", &self.blob, blob],
+        )?;
+        let hunks = diff
+            .windows(3)
+            .position(|w| w == b"\n@@")
+            .map_or(&[][..], |at| &diff[at + 1..]);
+        let mut patch = format!(
+            "diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n",
+            self.relative
+        )
+        .into_bytes();
+        patch.extend_from_slice(hunks);
+        Ok(patch)
+    }
+
+    pub fn apply_to_worktree(&self, patch: &[u8], check: bool) -> Result<()> {
+        let args: &[&str] = if check {
+            &["apply", "--check", "-"]
+        } else {
+            &["apply", "-"]
+        };
+        git_with_input(&self.toplevel, args, Some(patch))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::git::git;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HOOK_MARKER: &str = "# Installed by xzenfmt hook install";
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+pub fn hook_path(root: &Path) -> Result<PathBuf> {
+    let dir = if root.is_dir() {
+        root
+    } else {
+        root.parent().unwrap_or(root)
+    };
+    let hooks = git(dir, &["rev-parse", "--git-path", "hooks"])?;
+    let hooks = PathBuf::from(String::from_utf8_lossy(&hooks).trim());
+    let hooks = if dir == Path::new(".") {
+        hooks
+    } else {
+        dir.join(hooks)
+    };
+    Ok(hooks.join("pre-commit"))
+}
+
+pub fn render_hook(exe: &Path, args: &[String]) -> String {
+    let command: Vec<String> = [exe.to_string_lossy().as_ref(), "--no-confirm", "--index"]
+        .into_iter()
+        .chain(args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect();
+    format!("#!/bin/sh\n{}\nexec {}\n", HOOK_MARKER, command.join(" "))
+}
+
+pub fn install_hook(root: &Path, exe: &Path, args: &[String], force: bool) -> Result<PathBuf> {
+    let path = hook_path(root)?;
+    if let Ok(existing) = fs::read_to_string(&path)
+        && !existing.contains(HOOK_MARKER)
+        && !force
+    {
+        anyhow::bail!(
+            "{} already exists and was not written by xzenfmt (use --force to replace it)",
+            path.display()
+        );
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Create {}", dir.display()))?;
+    }
+    fs::write(&path, render_hook(exe, args))
+        .with_context(|| format!("Write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Make {} executable", path.display()))?;
+    }
+    Ok(path)
+}
+
+pub fn uninstall_hook(root: &Path) -> Result<Option<PathBuf>> {
+    let path = hook_path(root)?;
+    match fs::read_to_string(&path) {
+        Ok(existing) if existing.contains(HOOK_MARKER) => {
+            fs::remove_file(&path).with_context(|| format!("Remove {}", path.display()))?;
+            Ok(Some(path))
+        }
+        Ok(_) => anyhow::bail!("{} was not written by xzenfmt", path.display()),
+        Err(_) => Ok(None),
+    }
+}
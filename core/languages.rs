@@ -0,0 +1,466 @@
+use crate::config::Config;
+use crate::detect;
+use crate::formatters::FormatterRegistry;
+use CommentSyntax::*;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommentSyntax {
+    CFamily,
+    Rust,
+    Json,
+    Ruby,
+    Crystal,
+    Fish,
+    Shell,
+    Python,
+    Yaml,
+    Toml,
+    Lua,
+    Haskell,
+    Nix,
+    Twig,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Language {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub extensions: &'static [&'static str],
+    pub filenames: &'static [&'static str],
+    pub globs: &'static [&'static str],
+    pub interpreters: &'static [&'static str],
+    pub comments: Option<CommentSyntax>,
+    pub formatters: &'static [&'static str],
+    pub format: bool,
+}
+
+const fn language(
+    name: &'static str,
+    extensions: &'static [&'static str],
+    comments: Option<CommentSyntax>,
+    formatters: &'static [&'static str],
+) -> Language {
+    Language {
+        name,
+        aliases: &[],
+        extensions,
+        filenames: &[],
+        globs: &[],
+        interpreters: &[],
+        comments,
+        formatters,
+        format: true,
+    }
+}
+
+const SNIFFED_EXTENSIONS: &[&str] = &["in"];
+
+const PRETTIER: &[&str] = &["prettierd", "prettier"];
+const PRETTIER_OR_BIOME: &[&str] = &["prettierd", "prettier", "biome"];
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        aliases: &["rs", "rustic"],
+        interpreters: &["rust-script"],
+        ..language("rust", &["rs"], Some(Rust), &["rustfmt"])
+    },
+    language("c", &["c", "h"], Some(CFamily), &["astyle", "clang-format"]),
+    Language {
+        aliases: &["c++"],
+        ..language(
+            "cpp",
+            &["cpp", "cxx", "cc", "hpp"],
+            Some(CFamily),
+            &["astyle-google", "clang-format"],
+        )
+    },
+    Language {
+        aliases: &["rb"],
+        filenames: &[
+            "Rakefile",
+            "Gemfile",
+            "Brewfile",
+            "Vagrantfile",
+            "Guardfile",
+            "Podfile",
+            "Capfile",
+            ".irbrc",
+            ".pryrc",
+        ],
+        interpreters: &["ruby", "jruby", "truffleruby"],
+        ..language(
+            "ruby",
+            &["rb", "rake", "gemspec", "ru"],
+            Some(Ruby),
+            &["rubocop"],
+        )
+    },
+    Language {
+        aliases: &["conf-toml"],
+        ..language("toml", &["toml"], Some(Toml), &["taplo", "xzenfmt-toml"])
+    },
+    Language {
+        aliases: &["jsonc"],
+        ..language(
+            "json",
+            &["json", "jsonc"],
+            Some(Json),
+            &["prettierd", "prettier", "xzenfmt-json"],
+        )
+    },
+    Language {
+        aliases: &["yml"],
+        ..language(
+            "yaml",
+            &["yaml", "yml"],
+            Some(Yaml),
+            &["prettierd", "prettier", "xzenfmt-yaml"],
+        )
+    },
+    Language {
+        aliases: &["py"],
+        interpreters: &["python", "pypy"],
+        ..language("python", &["py"], Some(Python), &["black", "ruff"])
+    },
+    language("go", &["go"], Some(CFamily), &["gofmt"]),
+    Language {
+        interpreters: &["lua", "luajit"],
+        ..language("lua", &["lua"], Some(Lua), &["stylua"])
+    },
+    Language {
+        aliases: &["sh", "bash", "shell-script"],
+        filenames: &[
+            ".bashrc",
+            ".bash_profile",
+            ".bash_login",
+            ".bash_logout",
+            ".bash_aliases",
+            ".profile",
+            ".envrc",
+            "PKGBUILD",
+        ],
+        interpreters: &["sh", "bash", "dash", "ksh", "ash", "mksh"],
+        ..language("shell", &["sh", "bash"], Some(Shell), &["shfmt"])
+    },
+    Language {
+        interpreters: &["fish"],
+        ..language("fish", &["fish"], Some(Fish), &["fish_indent"])
+    },
+    Language {
+        aliases: &["cperl"],
+        interpreters: &["perl"],
+        ..language("perl", &["pl", "pm"], Some(Shell), &["perltidy"])
+    },
+    Language {
+        aliases: &["hs"],
+        interpreters: &["runhaskell", "runghc"],
+        ..language("haskell", &["hs", "lhs"], Some(Haskell), &["ormolu"])
+    },
+    Language {
+        format: false,
+        ..language("cabal", &["cabal"], None, &["cabal-fmt"])
+    },
+    language("elm", &["elm"], Some(Haskell), &["elm-format"]),
+    Language {
+        aliases: &["cr"],
+        interpreters: &["crystal"],
+        ..language("crystal", &["cr"], Some(Crystal), &["crystal"])
+    },
+    language("java", &["java"], Some(CFamily), &["google-java-format"]),
+    Language {
+        aliases: &["kt"],
+        ..language("kotlin", &["kt", "kts"], Some(CFamily), &["ktlint"])
+    },
+    Language {
+        interpreters: &["swift"],
+        ..language("swift", &["swift"], Some(CFamily), &["swift-format"])
+    },
+    Language {
+        aliases: &["js", "jsx"],
+        interpreters: &["node", "nodejs"],
+        ..language(
+            "javascript",
+            &["js", "mjs", "cjs", "jsx"],
+            Some(CFamily),
+            PRETTIER_OR_BIOME,
+        )
+    },
+    Language {
+        aliases: &["ts", "tsx"],
+        interpreters: &["deno", "ts-node", "tsx", "bun"],
+        ..language(
+            "typescript",
+            &["ts", "mts", "cts", "tsx"],
+            Some(CFamily),
+            PRETTIER_OR_BIOME,
+        )
+    },
+    Language {
+        aliases: &["md", "gfm"],
+        ..language("markdown", &["md", "markdown"], None, PRETTIER)
+    },
+    Language {
+        aliases: &["htm"],
+        ..language("html", &["html", "htm"], None, &["tidy"])
+    },
+    Language {
+        aliases: &["nxml"],
+        ..language("xml", &["xml", "xhtml"], None, &["tidy"])
+    },
+    language("css", &["css"], Some(CFamily), PRETTIER),
+    language("scss", &["scss"], Some(CFamily), PRETTIER),
+    language("less", &["less"], Some(CFamily), PRETTIER),
+    language("nix", &["nix"], Some(Nix), &["nixfmt", "alejandra"]),
+    language("twig", &["twig"], Some(Twig), &["djlint"]),
+    Language {
+        format: false,
+        ..language("conf", &["conf"], Some(Shell), &["nginxfmt"])
+    },
+    Language {
+        aliases: &["docker", "containerfile"],
+        filenames: &["Dockerfile", "Containerfile"],
+        globs: &["Dockerfile.*", "Containerfile.*", "*.dockerfile"],
+        ..language("dockerfile", &[], Some(Shell), &["dockfmt"])
+    },
+    Language {
+        aliases: &["justfile"],
+        filenames: &["Justfile", "justfile", ".justfile"],
+        ..language("just", &["just"], Some(Shell), &["just"])
+    },
+    Language {
+        aliases: &["asm", "nasm"],
+        format: false,
+        ..language("assembly", &["asm", "s"], None, &["asmfmt"])
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|l| {
+        l.name.eq_ignore_ascii_case(name) || l.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    })
+}
+
+pub fn formats_by_default(name: &str, config: &Config) -> bool {
+    let user = config.language(name);
+    user.and_then(|c| c.format).unwrap_or_else(|| {
+        user.is_some_and(|c| c.formatter.is_some() || c.pipeline.is_some())
+            || lookup(name).is_none_or(|l| l.format)
+    })
+}
+
+pub fn for_interpreter(interpreter: &str) -> Option<&'static Language> {
+    LANGUAGES
+        .iter()
+        .find(|l| l.interpreters.contains(&interpreter))
+}
+
+fn glob_matches(pattern: &str, file_name: &str) -> bool {
+    match (pattern.strip_prefix('*'), pattern.strip_suffix('*')) {
+        (Some(suffix), _) => file_name.len() > suffix.len() && file_name.ends_with(suffix),
+        (_, Some(prefix)) => file_name.len() > prefix.len() && file_name.starts_with(prefix),
+        _ => pattern == file_name,
+    }
+}
+
+fn known_language<'a>(path: &Path, config: &'a Config) -> Option<&'a str> {
+    let file_name = path.file_name().map(|n| n.to_string_lossy());
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    if let Some(lang) = file_name
+        .as_deref()
+        .and_then(|n| config.language_for_filename(n))
+    {
+        return Some(lang);
+    }
+    if let Some(lang) = extension
+        .as_deref()
+        .and_then(|e| config.language_for_extension(e))
+    {
+        return Some(lang);
+    }
+    if let Some(name) = file_name.as_deref() {
+        let by_name = LANGUAGES
+            .iter()
+            .find(|l| l.filenames.contains(&name))
+            .or_else(|| {
+                LANGUAGES
+                    .iter()
+                    .find(|l| l.globs.iter().any(|g| glob_matches(g, name)))
+            });
+        if let Some(l) = by_name {
+            return Some(l.name);
+        }
+    }
+    let extension = extension?;
+    LANGUAGES
+        .iter()
+        .find(|l| l.extensions.contains(&extension.as_str()))
+        .map(|l| l.name)
+}
+
+fn is_sniffable(path: &Path) -> bool {
+    path.extension()
+        .is_none_or(|e| SNIFFED_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+}
+
+pub fn language_for_path<'a>(path: &Path, config: &'a Config) -> Option<&'a str> {
+    known_language(path, config)
+        .or_else(|| is_sniffable(path).then(|| detect::language_from_file(path, config))?)
+}
+
+pub fn language_for_content<'a>(path: &Path, content: &str, config: &'a Config) -> Option<&'a str> {
+    known_language(path, config)
+        .or_else(|| is_sniffable(path).then(|| detect::language_from_content(content, config))?)
+}
+
+pub fn supported_languages(config: &Config) -> Vec<String> {
+    LANGUAGES
+        .iter()
+        .map(|l| l.name.to_string())
+        .chain(config.languages.keys().cloned())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LanguagesFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LanguageInfo {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    pub globs: Vec<String>,
+    pub interpreters: Vec<String>,
+    pub comments: Option<CommentSyntax>,
+    pub format: bool,
+    pub formatters: Vec<Vec<String>>,
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|s| s.to_string()).collect()
+}
+
+pub fn describe_languages(config: &Config) -> Vec<LanguageInfo> {
+    let registry = FormatterRegistry::new(config);
+    let mut names = supported_languages(config);
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            let builtin = LANGUAGES.iter().find(|l| l.name == name);
+            let user = config.language(&name);
+            let mut extensions = builtin.map_or_else(Vec::new, |l| strings(l.extensions));
+            let mut filenames = builtin.map_or_else(Vec::new, |l| strings(l.filenames));
+            if let Some(user) = user {
+                extensions.extend(
+                    user.extensions
+                        .iter()
+                        .map(|e| e.trim_start_matches('.').to_lowercase()),
+                );
+                filenames.extend(user.filenames.iter().cloned());
+            }
+            let formatters = registry
+                .steps(&name)
+                .iter()
+                .map(|step| step.iter().map(|spec| spec.name.clone()).collect())
+                .collect();
+            LanguageInfo {
+                aliases: builtin.map_or_else(Vec::new, |l| strings(l.aliases)),
+                extensions,
+                filenames,
+                globs: builtin.map_or_else(Vec::new, |l| strings(l.globs)),
+                interpreters: builtin.map_or_else(Vec::new, |l| strings(l.interpreters)),
+                comments: builtin.and_then(|l| l.comments),
+                format: formats_by_default(&name, config),
+                formatters,
+                name,
+            }
+        })
+        .collect()
+}
+
+pub fn render_languages(languages: &[LanguageInfo], format: LanguagesFormat) -> Result<String> {
+    if format == LanguagesFormat::Json {
+        let mut out = serde_json::to_string_pretty(languages).context("Serialize languages")?;
+        out.push('\n');
+        return Ok(out);
+    }
+    let header = [
+        "Language",
+        "Aliases",
+        "Strip",
+        "Format",
+        "Formatters",
+        "Files",
+    ]
+    .map(String::from);
+    let rows: Vec<[String; 6]> = languages
+        .iter()
+        .map(|l| {
+            let files: Vec<String> = l
+                .extensions
+                .iter()
+                .map(|e| format!("*.{}", e))
+                .chain(l.filenames.iter().cloned())
+                .chain(l.globs.iter().cloned())
+                .chain(l.interpreters.iter().map(|i| format!("#!{}", i)))
+                .collect();
+            let formatters: Vec<String> = l.formatters.iter().map(|step| step.join("|")).collect();
+            [
+                l.name.clone(),
+                l.aliases.join(", "),
+                if l.comments.is_some() { "yes" } else { "no" }.to_string(),
+                if l.format { "yes" } else { "no" }.to_string(),
+                formatters.join(" -> "),
+                files.join(" "),
+            ]
+        })
+        .collect();
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opt_in_languages_are_not_formatted_by_default() {
+        let config = Config::default();
+        assert!(formats_by_default("rust", &config));
+        for lang in ["conf", "assembly", "cabal"] {
+            assert!(!formats_by_default(lang, &config), "{lang}");
+        }
+        let config: Config = toml::from_str(
+            "[languages.conf]\nformat = true\n[languages.assembly]\nformatter = \"asmfmt\"\n[languages.rust]\nformat = false\n",
+        )
+        .unwrap();
+        assert!(formats_by_default("conf", &config));
+        assert!(formats_by_default("assembly", &config));
+        assert!(!formats_by_default("rust", &config));
+    }
+}
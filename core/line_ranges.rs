@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub const WHOLE_FILE: LineRange = LineRange {
+        start: 1,
+        end: usize::MAX,
+    };
+}
+
+impl FromStr for LineRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid line number '{}' (lines start at 1)", n))
+        };
+        let (start, end) = match s.split_once(':') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if start > end {
+            return Err(format!("Line range {} ends before it starts", s));
+        }
+        Ok(LineRange { start, end })
+    }
+}
+
+pub fn normalize(mut ranges: Vec<LineRange>) -> Vec<LineRange> {
+    ranges.sort();
+    let mut merged: Vec<LineRange> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end.saturating_add(1) => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+pub fn overlaps(ranges: &[LineRange], start: usize, end: usize) -> bool {
+    ranges.iter().any(|r| r.start <= end && start <= r.end)
+}
+
+pub fn contains(ranges: &[LineRange], line: usize) -> bool {
+    overlaps(ranges, line, line)
+}
+
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { starts }
+    }
+
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn span(&self, text: &str, ranges: &[LineRange]) -> Option<(usize, usize)> {
+        let first = ranges.first()?.start;
+        let last = ranges.last()?.end;
+        let start = *self.starts.get(first - 1)?;
+        let end = self.starts.get(last).map_or(text.len(), |&next| next);
+        Some((start, end))
+    }
+}
+
+const MAX_DIFF_EDITS: usize = 1024;
+
+fn diff_lines(a: &[&str], b: &[&str]) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let off = n + m + 1;
+    let mut v = vec![0isize; 2 * off as usize + 1];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut edits = None;
+    'search: for d in 0..=(n + m).min(MAX_DIFF_EDITS as isize) {
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[(k - 1 + off) as usize] < v[(k + 1 + off) as usize]);
+            let mut x = if down {
+                v[(k + 1 + off) as usize]
+            } else {
+                v[(k - 1 + off) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + off) as usize] = x;
+            if x >= n && y >= m {
+                edits = Some(d);
+                break 'search;
+            }
+        }
+        trace.push(v[(off - d) as usize..=(off + d) as usize].to_vec());
+    }
+    let edits = edits?;
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..=edits).rev() {
+        let prev = &trace[d as usize - 1];
+        let at = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        pairs.push((x as usize, y as usize));
+    }
+    pairs.reverse();
+    Some(pairs)
+}
+
+fn edge_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (0..prefix)
+        .map(|i| (i, i))
+        .chain(
+            (0..suffix)
+                .rev()
+                .map(|i| (a.len() - 1 - i, b.len() - 1 - i)),
+        )
+        .collect()
+}
+
+pub fn remap(old: &str, new: &str, ranges: &[LineRange]) -> Vec<LineRange> {
+    if old == new {
+        return ranges.to_vec();
+    }
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let pairs = diff_lines(&a, &b).unwrap_or_else(|| edge_lines(&a, &b));
+    let mut matched = vec![None; a.len()];
+    for (x, y) in pairs {
+        matched[x] = Some(y);
+    }
+    let mut mapped = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        let first = r.start - 1;
+        let last = r.end.min(a.len()).saturating_sub(1);
+        let start = matched[..first.min(a.len())]
+            .iter()
+            .rev()
+            .find_map(|y| *y)
+            .map_or(1, |y| y + 2);
+        let end = matched
+            .get(last + 1..)
+            .and_then(|rest| rest.iter().find_map(|y| *y))
+            .unwrap_or(b.len());
+        if start <= end {
+            mapped.push(LineRange { start, end });
+        }
+    }
+    normalize(mapped)
+}
+
+#[derive(Debug, Clone)]
+pub enum LineSelection {
+    All(Vec<LineRange>),
+    PerFile(HashMap<PathBuf, Vec<LineRange>>),
+}
+
+impl LineSelection {
+    pub fn for_path(&self, path: &Path) -> &[LineRange] {
+        match self {
+            LineSelection::All(ranges) => ranges,
+            LineSelection::PerFile(files) => path
+                .canonicalize()
+                .ok()
+                .and_then(|path| files.get(&path))
+                .map_or(&[], Vec::as_slice),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(start: usize, end: usize) -> LineRange {
+        LineRange { start, end }
+    }
+
+    #[test]
+    fn remap_keeps_ranges_of_unchanged_text() {
+        assert_eq!(remap("a\nb\n", "a\nb\n", &[lines(2, 2)]), [lines(2, 2)]);
+    }
+
+    #[test]
+    fn remap_follows_inserted_and_removed_lines() {
+        let old = "a\nb\nc\nd\n";
+        assert_eq!(
+            remap(old, "x\ny\na\nb\nc\nd\n", &[lines(2, 3)]),
+            [lines(4, 5)]
+        );
+        assert_eq!(remap(old, "b\nc\nd\n", &[lines(3, 4)]), [lines(2, 3)]);
+    }
+
+    #[test]
+    fn remap_covers_rewritten_lines() {
+        let old = "a\nb\nc\nd\n";
+        assert_eq!(
+            remap(old, "a\nB\nB2\nc\nd\n", &[lines(2, 2)]),
+            [lines(2, 3)]
+        );
+        assert_eq!(remap(old, "a\nd\n", &[lines(2, 3)]), []);
+    }
+
+    #[test]
+    fn remap_merges_ranges_that_meet() {
+        let old = "a\nb\nc\nd\ne\n";
+        assert_eq!(
+            remap(old, "a\nb\nd\ne\n", &[lines(2, 2), lines(4, 4)]),
+            [lines(2, 3)]
+        );
+    }
+}
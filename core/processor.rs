@@ -1,27 +1,344 @@
-use crate::command_runner::*;
-use crate::stripper::{self, StripError};
-use anyhow::Result;
+use crate::backup::BackupRun;
+use crate::cache::ResultCache;
+use crate::command_runner::ToolError;
+use crate::comment_heuristics;
+use crate::config::{Config, ConfigResolver};
+use crate::detect;
+use crate::formatters::{FormatterRegistry, FormatterSpec};
+use crate::git::StagedFile;
+use crate::languages::{self, language_for_content, language_for_path};
+use crate::line_ranges::{self, LineIndex, LineRange, LineSelection};
+use crate::safe_write::{validate_output, write_atomically};
+use crate::stripper::{self, CommentMatch, StripError};
+use anyhow::{Context, Result};
 use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum OperationMode {
     Format,
     Strip,
     StripWhitespace,
     StripNewlines,
+    StripDeadCode,
+    StripRedundant,
+    All,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    clap::ValueEnum,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommentStripMode {
+    FullLine,
+    Trailing,
+    #[default]
     All,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    pub comments: Option<CommentStripMode>,
+    pub explain: bool,
+    pub config: Config,
+    pub backup: Option<Arc<BackupRun>>,
+    pub cache: Option<Arc<ResultCache>>,
+    pub skip_strip_validation: bool,
+    pub verify: bool,
+    pub lines: Option<Arc<LineSelection>>,
+    pub index: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommentSelection {
+    Every,
+    DeadCode,
+    Redundant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Format,
+    Strip,
+    Reformat,
+    Verify,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ProcessError {
+    #[error("{operation} failed for {}: permission denied", .path.display())]
+    PermissionDenied {
+        path: PathBuf,
+        operation: &'static str,
+    },
+    #[error("{operation} failed for {}: {message}", .path.display())]
+    Io {
+        path: PathBuf,
+        operation: &'static str,
+        message: String,
+    },
+    #[error("{} is not valid UTF-8 (invalid byte at offset {offset})", .path.display())]
+    NotUtf8 { path: PathBuf, offset: usize },
+    #[error("No formatter available for {} ({language}; tried: {})", .path.display(), .tried.join(", "))]
+    FormatterMissing {
+        path: PathBuf,
+        language: String,
+        tried: Vec<String>,
+    },
+    #[error("Formatter '{tool}' cannot format only the selected lines of {} ({language})", .path.display())]
+    RangesUnsupported {
+        path: PathBuf,
+        language: String,
+        tool: String,
+    },
+    #[error("Formatter '{tool}' failed for {}{}:\n{stderr}", .path.display(), .exit_code.map(|c| format!(" with exit code {}", c)).unwrap_or_default())]
+    FormatterFailed {
+        path: PathBuf,
+        language: String,
+        tool: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+    #[error("Formatter '{tool}' timed out after {}s on {}", *.timeout_ms as f64 / 1000.0, .path.display())]
+    TimedOut {
+        path: PathBuf,
+        language: String,
+        tool: String,
+        timeout_ms: u64,
+    },
+    #[error("Formatting {} is not idempotent: a second run of {} changed it again at byte {offset}", .path.display(), .formatters.join(", "))]
+    NotIdempotent {
+        path: PathBuf,
+        language: String,
+        formatters: Vec<String>,
+        offset: usize,
+    },
+    #[error("Comment stripping failed for {} ({language}): {error}", .path.display())]
+    Strip {
+        path: PathBuf,
+        language: String,
+        offset: usize,
+        error: StripError,
+    },
+    #[error("Refusing to write {}: {message}", .path.display())]
+    InvalidOutput { path: PathBuf, message: String },
+    #[error("Backup failed for {}: {message}", .path.display())]
+    Backup { path: PathBuf, message: String },
+    #[error("Invalid configuration for {}: {message}", .path.display())]
+    Config { path: PathBuf, message: String },
+}
+
+impl ProcessError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessError::PermissionDenied { .. } => "permission-denied",
+            ProcessError::Io { .. } => "io",
+            ProcessError::NotUtf8 { .. } => "not-utf8",
+            ProcessError::FormatterMissing { .. } => "formatter-missing",
+            ProcessError::RangesUnsupported { .. } => "ranges-unsupported",
+            ProcessError::FormatterFailed { .. } => "formatter-failed",
+            ProcessError::TimedOut { .. } => "timed-out",
+            ProcessError::NotIdempotent { .. } => "not-idempotent",
+            ProcessError::Strip { .. } => "strip",
+            ProcessError::InvalidOutput { .. } => "invalid-output",
+            ProcessError::Backup { .. } => "backup",
+            ProcessError::Config { .. } => "config",
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            ProcessError::PermissionDenied { path, .. }
+            | ProcessError::Io { path, .. }
+            | ProcessError::NotUtf8 { path, .. }
+            | ProcessError::FormatterMissing { path, .. }
+            | ProcessError::RangesUnsupported { path, .. }
+            | ProcessError::FormatterFailed { path, .. }
+            | ProcessError::TimedOut { path, .. }
+            | ProcessError::NotIdempotent { path, .. }
+            | ProcessError::Strip { path, .. }
+            | ProcessError::InvalidOutput { path, .. }
+            | ProcessError::Backup { path, .. }
+            | ProcessError::Config { path, .. } => path,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ProcessError::TimedOut { .. })
+    }
+
+    fn io(path: &Path, operation: &'static str, error: &io::Error) -> Self {
+        if error.kind() == io::ErrorKind::PermissionDenied {
+            ProcessError::PermissionDenied {
+                path: path.to_path_buf(),
+                operation,
+            }
+        } else {
+            ProcessError::Io {
+                path: path.to_path_buf(),
+                operation,
+                message: error.to_string(),
+            }
+        }
+    }
+
+    fn from_anyhow(path: &Path, operation: &'static str, error: &anyhow::Error) -> Self {
+        match error.chain().find_map(|e| e.downcast_ref::<io::Error>()) {
+            Some(e) if e.kind() == io::ErrorKind::PermissionDenied => Self::io(path, operation, e),
+            _ => ProcessError::Io {
+                path: path.to_path_buf(),
+                operation,
+                message: format!("{:#}", error),
+            },
+        }
+    }
+
+    fn from_tool(path: &Path, language: &str, error: ToolError) -> Self {
+        let path = path.to_path_buf();
+        let language = language.to_string();
+        match error {
+            ToolError::Unavailable { tried, .. } => ProcessError::FormatterMissing {
+                path,
+                language,
+                tried,
+            },
+            ToolError::RangesUnsupported { tool, .. } => ProcessError::RangesUnsupported {
+                path,
+                language,
+                tool,
+            },
+            ToolError::Spawn { tool, source } if source.kind() == io::ErrorKind::NotFound => {
+                ProcessError::FormatterMissing {
+                    path,
+                    language,
+                    tried: vec![tool],
+                }
+            }
+            ToolError::Spawn { tool, source } => ProcessError::FormatterFailed {
+                path,
+                language,
+                tool,
+                exit_code: None,
+                stderr: source.to_string(),
+            },
+            ToolError::TimedOut { tool, timeout } => ProcessError::TimedOut {
+                path,
+                language,
+                tool,
+                timeout_ms: timeout.as_millis() as u64,
+            },
+            ToolError::Rejected { tool, code, stderr } => ProcessError::FormatterFailed {
+                path,
+                language,
+                tool,
+                exit_code: code,
+                stderr,
+            },
+            ToolError::Io { tool, source } => ProcessError::Io {
+                path,
+                operation: "Run formatter",
+                message: format!("{}: {}", tool, source),
+            },
+        }
+    }
+
+    fn strip(path: &Path, language: &str, error: StripError) -> Self {
+        ProcessError::Strip {
+            path: path.to_path_buf(),
+            language: language.to_string(),
+            offset: error.offset(),
+            error,
+        }
+    }
+}
+
+fn read_source(path: &Path) -> Result<String, ProcessError> {
+    let bytes = fs::read(path).map_err(|e| ProcessError::io(path, "Read", &e))?;
+    decode_source(path, bytes)
+}
+
+fn decode_source(path: &Path, bytes: Vec<u8>) -> Result<String, ProcessError> {
+    String::from_utf8(bytes).map_err(|e| ProcessError::NotUtf8 {
+        path: path.to_path_buf(),
+        offset: e.utf8_error().valid_up_to(),
+    })
+}
+
+fn validate_result(path: &Path, original: &str, output: &str) -> Result<(), ProcessError> {
+    validate_output(original, output).map_err(|e| ProcessError::InvalidOutput {
+        path: path.to_path_buf(),
+        message: format!("{:#}", e),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileOutcome {
+    Unchanged,
+    Changed,
+    Cached,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FileMetrics {
+    pub bytes_removed: i64,
+    pub lines_removed: i64,
+    pub comments_removed: usize,
+    pub formatters: Vec<String>,
+    pub failed_stage: Option<Stage>,
+}
+
+struct FileRun {
+    outcome: Result<FileOutcome, ProcessError>,
+    metrics: FileMetrics,
+    duration: Duration,
+}
+
+impl FileRun {
+    fn early(outcome: Result<FileOutcome, ProcessError>) -> Self {
+        FileRun {
+            outcome,
+            metrics: FileMetrics::default(),
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct ProcessedFileResult {
     pub path: PathBuf,
-    pub error: Option<String>,
+    pub language: Option<String>,
+    pub mode: OperationMode,
+    pub changed: bool,
+    pub cached: bool,
+    #[serde(flatten)]
+    pub metrics: FileMetrics,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    pub error: Option<ProcessError>,
 }
 
-fn remove_trailing_whitespace(text: &str) -> String {
+fn serialize_millis<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64() * 1000.0)
+}
+
+fn remove_trailing_whitespace(text: &str, lines: Option<&[LineRange]>) -> String {
     if text.is_empty() {
         return String::new();
     }
@@ -30,24 +347,28 @@ fn remove_trailing_whitespace(text: &str) -> String {
         if i > 0 {
             r.push('\n');
         }
+        if lines.is_some_and(|lines| !line_ranges::contains(lines, i + 1)) {
+            r.push_str(l);
+            continue;
+        }
         r.push_str(l.trim_end_matches(|c: char| c.is_whitespace() && c != '\n'));
     }
     if text.ends_with('\n') && !r.is_empty() && !r.ends_with('\n') {
         r.push('\n');
     }
-    if text.trim().is_empty() && text.ends_with('\n') && !text.is_empty() {
+    if text.trim().is_empty() && text.ends_with('\n') && !text.is_empty() && lines.is_none() {
         return "\n".to_string();
     }
 
     r
 }
 
-fn collapse_blank_lines(text: &str) -> String {
+fn collapse_blank_lines(text: &str, lines: Option<&[LineRange]>) -> String {
     let mut result = String::with_capacity(text.len());
     let mut consecutive_blank_lines = 0;
     let mut first_line = true;
 
-    for line in text.lines() {
+    for (i, line) in text.lines().enumerate() {
         let trimmed_line = line.trim();
         let is_blank = trimmed_line.is_empty();
 
@@ -57,7 +378,8 @@ fn collapse_blank_lines(text: &str) -> String {
             consecutive_blank_lines = 0;
         }
 
-        if consecutive_blank_lines <= 1 {
+        let selected = lines.is_none_or(|lines| line_ranges::contains(lines, i + 1));
+        if consecutive_blank_lines <= 1 || !selected {
             if !first_line {
                 result.push('\n');
             }
@@ -69,297 +391,902 @@ fn collapse_blank_lines(text: &str) -> String {
     if text.ends_with('\n') && !result.is_empty() && !result.ends_with('\n') {
         result.push('\n');
     }
-    if text.chars().all(|c| c == '\n') && !text.is_empty() {
+    if text.chars().all(|c| c == '\n') && !text.is_empty() && lines.is_none() {
         return "\n".to_string();
     }
 
     result
 }
 
-fn get_language_from_path(path: &Path) -> Option<&str> {
-    if let Some(n) = path.file_name() {
-        let s = n.to_string_lossy();
-        match s.as_ref() {
-            "Rakefile" | "Gemfile" => return Some("ruby"),
-            "Dockerfile" => return Some("dockerfile"),
-            _ => {}
-        }
-    }
-    path.extension()
-        .and_then(|e| e.to_str())
-        .map(|e| e.to_lowercase())
-        .and_then(|e| match e.as_str() {
-            "rs" => Some("rust"),
-            "c" | "h" => Some("c"),
-            "cpp" | "cxx" | "cc" | "hpp" => Some("cpp"),
-            "rb" | "rake" => Some("ruby"),
-            "toml" => Some("toml"),
-            "json" | "jsonc" => Some("json"),
-            "yaml" | "yml" => Some("yaml"),
-            "py" => Some("python"),
-            "go" => Some("go"),
-            "lua" => Some("lua"),
-            "sh" | "bash" => Some("shell"),
-            "fish" => Some("fish"),
-            "pl" | "pm" => Some("perl"),
-            "hs" | "lhs" => Some("haskell"),
-            "cabal" => Some("cabal"),
-            "elm" => Some("elm"),
-            "cr" => Some("crystal"),
-            "java" => Some("java"),
-            "kt" | "kts" => Some("kotlin"),
-            "swift" => Some("swift"),
-            "md" | "markdown" => Some("markdown"),
-            "html" | "htm" => Some("html"),
-            "xml" | "xhtml" => Some("xml"),
-            "css" => Some("css"),
-            "scss" => Some("scss"),
-            "less" => Some("less"),
-            "nix" => Some("nix"),
-            "twig" => Some("twig"),
-            "conf" => Some("conf"),
-            "asm" | "s" => Some("assembly"),
-            _ => None,
-        })
+fn line_start(input: &str, pos: usize) -> usize {
+    input[..pos].rfind('\n').map_or(0, |i| i + 1)
 }
-fn map_err_to_string<E: std::fmt::Display>(p: &Path, c: &str) -> impl Fn(E) -> String {
-    let d = p.display().to_string();
-    move |e| format!("{} failed for {}: {}", c, d, e)
+
+fn line_end(input: &str, pos: usize) -> usize {
+    input[pos..].find('\n').map_or(input.len(), |i| pos + i)
 }
 
-fn strip_comments_smart(input: &str, lang: &str) -> Result<String, StripError> {
-    let matches = stripper::find_language_comments(input, lang, Path::new(""))?;
-    if matches.is_empty()
-        && !input.contains("\n//")
-        && !input.contains("\n/*")
-        && !input.contains("\n#")
-    {
-        return Ok(input.to_string());
+fn is_preserved_comment(text: &str, lang: &str) -> bool {
+    text.starts_with("#!")
+        || detect::is_modeline(text)
+        || (lang == "ruby" && text.trim() == "# frozen_string_literal: true")
+}
+
+fn has_code_between(code_mask: &[bool], input: &str, from: usize, to: usize) -> bool {
+    input[from..to]
+        .char_indices()
+        .any(|(i, c)| !c.is_whitespace() && code_mask[from + i])
+}
+
+fn merge_ranges(mut ranges: Vec<CommentMatch>) -> Vec<CommentMatch> {
+    ranges.sort_by_key(|m| m.from);
+    let mut merged: Vec<CommentMatch> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.from <= last.to => last.to = last.to.max(r.to),
+            _ => merged.push(r),
+        }
     }
+    merged
+}
 
-    let mut lines_to_keep = Vec::new();
-    let mut line_start_indices = vec![0];
-    line_start_indices.extend(input.match_indices('\n').map(|(i, _)| i + 1));
-    if !input.is_empty() && !input.ends_with('\n') {
-        line_start_indices.push(input.len());
+pub(crate) fn build_code_mask(input: &str, matches: &[CommentMatch]) -> Vec<bool> {
+    let mut code_mask = vec![true; input.len()];
+    for m in matches {
+        if let Some(masked) = code_mask.get_mut(m.from..m.to) {
+            masked.iter_mut().for_each(|b| *b = false);
+        }
     }
+    code_mask
+}
 
-    for window in line_start_indices.windows(2) {
-        let line_start = window[0];
-        let line_end = window[1];
+fn code_text(code_mask: &[bool], input: &str, from: usize, to: usize) -> String {
+    input[from..to]
+        .char_indices()
+        .filter(|(i, _)| code_mask[from + i])
+        .map(|(_, c)| c)
+        .collect()
+}
 
-        if line_start >= line_end {
-            continue;
+fn described_code(code_mask: &[bool], input: &str, m: &CommentMatch) -> String {
+    let start = line_start(input, m.from);
+    if has_code_between(code_mask, input, start, m.from) {
+        return code_text(code_mask, input, start, m.from);
+    }
+    let mut pos = line_end(input, m.to);
+    while pos < input.len() {
+        let next_start = pos + 1;
+        let next_end = line_end(input, next_start);
+        if has_code_between(code_mask, input, next_start, next_end) {
+            return code_text(code_mask, input, next_start, next_end);
         }
+        pos = next_end;
+    }
+    String::new()
+}
 
-        let line_content_with_ending = &input[line_start..line_end];
-        let line_content = line_content_with_ending.trim_end_matches('\n');
-        let trimmed_line = line_content.trim();
+fn comment_removal_ranges(
+    input: &str,
+    lang: &str,
+    matches: &[CommentMatch],
+    comments: CommentStripMode,
+) -> Vec<CommentMatch> {
+    let bytes = input.as_bytes();
+    let code_mask = build_code_mask(input, matches);
 
-        if trimmed_line.starts_with("#!")
-            || (lang == "ruby" && trimmed_line == "# frozen_string_literal: true")
+    let mut ranges = Vec::new();
+    for m in matches {
+        if m.from >= m.to || m.to > input.len() || is_preserved_comment(&input[m.from..m.to], lang)
         {
-            lines_to_keep.push(line_content_with_ending);
             continue;
         }
+        let start = line_start(input, m.from);
+        let end = line_end(input, m.to);
+        let code_before = has_code_between(&code_mask, input, start, m.from);
+        let code_after = has_code_between(&code_mask, input, m.to, end);
 
-        let trimmed_start_offset = line_content.find(trimmed_line).unwrap_or(0);
-        let absolute_trimmed_start = line_start + trimmed_start_offset;
-        let absolute_trimmed_end = absolute_trimmed_start + trimmed_line.len();
+        match (code_before, code_after) {
+            (false, false) => {
+                if comments != CommentStripMode::Trailing {
+                    let to = if end < input.len() { end + 1 } else { end };
+                    ranges.push(CommentMatch { from: start, to });
+                }
+            }
+            (true, false) => {
+                if comments != CommentStripMode::FullLine {
+                    let mut from = m.from;
+                    while from > start && matches!(bytes[from - 1], b' ' | b'\t') {
+                        from -= 1;
+                    }
+                    let mut to = m.to;
+                    while to < end && matches!(bytes[to], b' ' | b'\t' | b'\r') {
+                        to += 1;
+                    }
+                    ranges.push(CommentMatch { from, to });
+                }
+            }
+            (_, true) => {
+                if comments == CommentStripMode::All && !input[m.from..m.to].contains('\n') {
+                    let mut to = m.to;
+                    let preceded_by_space =
+                        m.from == start || matches!(bytes[m.from - 1], b' ' | b'\t');
+                    if preceded_by_space {
+                        while to < end && matches!(bytes[to], b' ' | b'\t') {
+                            to += 1;
+                        }
+                    }
+                    ranges.push(CommentMatch { from: m.from, to });
+                }
+            }
+        }
+    }
+    merge_ranges(ranges)
+}
+
+fn select_comments(
+    input: &str,
+    lang: &str,
+    matches: Vec<CommentMatch>,
+    selection: CommentSelection,
+    explain_path: Option<&Path>,
+) -> Vec<CommentMatch> {
+    if selection == CommentSelection::Every {
+        return matches;
+    }
+    let code_mask = build_code_mask(input, &matches);
+    let mut report = String::new();
+    let mut selected = Vec::new();
+    for m in matches {
+        if m.from >= m.to || m.to > input.len() {
+            continue;
+        }
+        let text = &input[m.from..m.to];
+        let (score, remove, label) = match selection {
+            CommentSelection::Redundant => {
+                let code = described_code(&code_mask, input, &m);
+                let r = comment_heuristics::classify_redundancy(text, &code);
+                let label = if r.is_redundant { "redundant" } else { "keep" };
+                (r.score, r.is_redundant, label)
+            }
+            _ => {
+                let c = comment_heuristics::classify_comment(text, lang);
+                (c.score, c.is_code, if c.is_code { "code" } else { "prose" })
+            }
+        };
+        if let Some(path) = explain_path {
+            let line = input[..m.from].matches('\n').count() + 1;
+            report.push_str(&format!(
+                "{}:{}: score={:.2} {:<9} {:?}\n",
+                path.display(),
+                line,
+                score,
+                label,
+                comment_heuristics::comment_body(text)
+            ));
+        }
+        if remove {
+            selected.push(m);
+        }
+    }
+    if !report.is_empty() {
+        eprint!("{}", report);
+    }
+    selected
+}
 
-        let is_comment_only = !trimmed_line.is_empty()
-            && matches
-                .iter()
-                .any(|m| m.from <= absolute_trimmed_start && m.to >= absolute_trimmed_end);
+#[derive(Debug, Clone, Copy)]
+struct StripSettings<'a> {
+    comments: CommentStripMode,
+    validate: bool,
+    lines: Option<&'a [LineRange]>,
+}
 
-        if !is_comment_only {
-            lines_to_keep.push(line_content_with_ending);
+fn strip_comments_smart(
+    input: &str,
+    lang: &str,
+    settings: StripSettings,
+    selection: CommentSelection,
+    explain_path: Option<&Path>,
+    metrics: &mut FileMetrics,
+) -> Result<String, StripError> {
+    let all_matches = stripper::find_language_comments(input, lang, Path::new(""))?;
+    if all_matches.is_empty() {
+        return Ok(input.to_string());
+    }
+    let mut candidates = all_matches.clone();
+    if let Some(lines) = settings.lines {
+        let index = LineIndex::new(input);
+        candidates.retain(|m| {
+            let last = m.to.saturating_sub(1).max(m.from);
+            line_ranges::overlaps(lines, index.line_of(m.from), index.line_of(last))
+        });
+    }
+    let matches = select_comments(input, lang, candidates, selection, explain_path);
+    let ranges = comment_removal_ranges(input, lang, &matches, settings.comments);
+    let removed = matches
+        .iter()
+        .filter(|m| ranges.iter().any(|r| r.from <= m.from && m.to <= r.to))
+        .count();
+    let output = stripper::remove_matches(input.to_string(), ranges.clone())?;
+    if settings.validate && output != input {
+        stripper::validate_stripped(input, all_matches, &ranges, &output, lang)?;
+    }
+    metrics.comments_removed += removed;
+    Ok(output)
+}
+fn work_dir_for(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => PathBuf::from("."),
+        Some(dir) if dir.is_dir() => dir.to_path_buf(),
+        _ => std::env::temp_dir(),
+    }
+}
+
+fn format_temp_file(
+    content: &str,
+    path: &Path,
+    work_dir: &Path,
+) -> Result<tempfile::TempPath, ProcessError> {
+    let suffix = path
+        .extension()
+        .map(|s| format!(".{}", s.to_string_lossy()))
+        .unwrap_or_default();
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(".xzen_fmt_")
+        .suffix(&suffix)
+        .tempfile_in(work_dir)
+        .map_err(|e| ProcessError::io(path, "Create format temp file", &e))?;
+    temp_file
+        .write_all(content.as_bytes())
+        .map_err(|e| ProcessError::io(path, "Write format temp file", &e))?;
+    Ok(temp_file.into_temp_path())
+}
+
+fn read_formatted(temp_path: &Path, path: &Path) -> Result<String, ProcessError> {
+    fs::read_to_string(temp_path).map_err(|e| {
+        if e.kind() == io::ErrorKind::InvalidData {
+            ProcessError::InvalidOutput {
+                path: path.to_path_buf(),
+                message: "formatter produced invalid UTF-8".to_string(),
+            }
+        } else {
+            ProcessError::io(path, "Read format temp file", &e)
+        }
+    })
+}
+
+fn format_content(
+    content: &str,
+    path: &Path,
+    lang: &str,
+    work_dir: &Path,
+    config: &Config,
+    ranges: Option<&[LineRange]>,
+    metrics: &mut FileMetrics,
+) -> Result<String, ProcessError> {
+    let temp_path_obj = format_temp_file(content, path, work_dir)?;
+    let used = FormatterRegistry::new(config)
+        .format_file(&temp_path_obj, lang, ranges)
+        .map_err(|e| ProcessError::from_tool(path, lang, e))?;
+    for name in used {
+        if !metrics.formatters.contains(&name) {
+            metrics.formatters.push(name);
         }
     }
+    read_formatted(&temp_path_obj, path)
+}
 
-    let filtered_content = lines_to_keep.join("");
+fn can_format(lang: &str, config: &Config) -> bool {
+    languages::formats_by_default(lang, config)
+        && !FormatterRegistry::new(config).steps(lang).is_empty()
+}
 
-    let final_matches = stripper::find_language_comments(&filtered_content, lang, Path::new(""))?;
+fn can_strip(lang: &str, config: &Config) -> bool {
+    config.strip_enabled()
+        && config
+            .language(lang)
+            .and_then(|c| c.strip)
+            .unwrap_or_else(|| languages::lookup(lang).is_some_and(|l| l.comments.is_some()))
+}
 
-    stripper::remove_matches(filtered_content, final_matches)
+fn verify_idempotent(
+    formatted: &str,
+    path: &Path,
+    lang: &str,
+    work_dir: &Path,
+    config: &Config,
+    ranges: Option<&[LineRange]>,
+) -> Result<(), ProcessError> {
+    let mut metrics = FileMetrics::default();
+    let again = format_content(
+        formatted,
+        path,
+        lang,
+        work_dir,
+        config,
+        ranges,
+        &mut metrics,
+    )?;
+    if again == formatted {
+        return Ok(());
+    }
+    let offset = formatted
+        .bytes()
+        .zip(again.bytes())
+        .position(|(a, b)| a != b)
+        .unwrap_or(formatted.len().min(again.len()));
+    Err(ProcessError::NotIdempotent {
+        path: path.to_path_buf(),
+        language: lang.to_string(),
+        formatters: metrics.formatters,
+        offset,
+    })
 }
-fn process_single_file(path: &Path, mode: OperationMode) -> Result<(), String> {
-    let lang = match get_language_from_path(path) {
-        Some(l) => l,
-        None => return Ok(()),
-    };
-    let can_format = !matches!(
+
+fn transform_content(
+    original_content: &str,
+    path: &Path,
+    lang: &str,
+    work_dir: &Path,
+    mode: OperationMode,
+    options: &ProcessOptions,
+    metrics: &mut FileMetrics,
+) -> Result<String, ProcessError> {
+    run_stages(
+        original_content,
+        path,
         lang,
-        "assembly" | "cabal" | "conf" | "erb" | "elisp" | "svelte" | "vue"
-    );
-    let can_strip = !matches!(lang, "assembly" | "erb" | "cabal" | "svelte" | "vue");
-    let can_clean_whitespace = true;
-    let can_clean_newlines = true;
+        work_dir,
+        mode,
+        options,
+        metrics,
+    )
+    .map_err(|(stage, e)| {
+        metrics.failed_stage = Some(stage);
+        e
+    })
+}
 
-    match mode {
-        OperationMode::Format | OperationMode::All if !can_format => return Ok(()),
-        OperationMode::Strip if !can_strip => return Ok(()),
-        OperationMode::StripWhitespace if !can_clean_whitespace => return Ok(()),
-        OperationMode::StripNewlines if !can_clean_newlines => return Ok(()),
-        _ => {}
+fn run_stages(
+    original_content: &str,
+    path: &Path,
+    lang: &str,
+    work_dir: &Path,
+    mode: OperationMode,
+    options: &ProcessOptions,
+    metrics: &mut FileMetrics,
+) -> Result<String, (Stage, ProcessError)> {
+    let can_format = can_format(lang, &options.config);
+    let can_strip = can_strip(lang, &options.config);
+    let lines = options.lines.as_ref().map(|lines| lines.for_path(path));
+    if lines.is_some_and(<[LineRange]>::is_empty) {
+        return Ok(original_content.to_string());
     }
-
-    let original_content = fs::read_to_string(path).map_err(map_err_to_string(path, "Read"))?;
-    let mut current_content = original_content.clone();
-    let mut modified = false;
+    let strip = StripSettings {
+        comments: options
+            .comments
+            .or(options.config.strip.comments)
+            .unwrap_or_default(),
+        validate: !options.skip_strip_validation && options.config.strip_validate(),
+        lines,
+    };
+    let at = |stage: Stage| move |e| (stage, e);
+    let verify = |formatted: String| {
+        if options.verify && formatted != original_content {
+            let ranges = lines.map(|lines| line_ranges::remap(original_content, &formatted, lines));
+            verify_idempotent(
+                &formatted,
+                path,
+                lang,
+                work_dir,
+                &options.config,
+                ranges.as_deref(),
+            )
+            .map_err(at(Stage::Verify))?;
+        }
+        Ok(formatted)
+    };
+    let can_clean_whitespace = true;
+    let can_clean_newlines = true;
+    let unchanged = || Ok(original_content.to_string());
 
     match mode {
         OperationMode::Format => {
             if !can_format {
-                return Ok(());
-            }
-            run_formatter_for_lang(path, lang)?;
-            current_content = fs::read_to_string(path)
-                .map_err(map_err_to_string(path, "Re-read after format"))?;
-            if current_content != original_content {
-                modified = true;
+                return unchanged();
             }
+            let formatted = format_content(
+                original_content,
+                path,
+                lang,
+                work_dir,
+                &options.config,
+                lines,
+                metrics,
+            )
+            .map_err(at(Stage::Format))?;
+            verify(formatted)
         }
         OperationMode::Strip => {
             if !can_strip {
-                return Ok(());
+                return unchanged();
             }
-            let stripped_content = strip_comments_smart(&current_content, lang)
-                .map_err(|e| format!("Smart stripping failed: {}", e))?;
-            if stripped_content != current_content {
-                current_content = stripped_content;
-                modified = true;
+            strip_comments_smart(
+                original_content,
+                lang,
+                strip,
+                CommentSelection::Every,
+                None,
+                metrics,
+            )
+            .map_err(|e| ProcessError::strip(path, lang, e))
+            .map_err(at(Stage::Strip))
+        }
+        OperationMode::StripDeadCode | OperationMode::StripRedundant => {
+            if !can_strip {
+                return unchanged();
             }
+            let selection = if mode == OperationMode::StripDeadCode {
+                CommentSelection::DeadCode
+            } else {
+                CommentSelection::Redundant
+            };
+            let explain_path = options.explain.then_some(path);
+            strip_comments_smart(
+                original_content,
+                lang,
+                strip,
+                selection,
+                explain_path,
+                metrics,
+            )
+            .map_err(|e| ProcessError::strip(path, lang, e))
+            .map_err(at(Stage::Strip))
         }
         OperationMode::StripWhitespace => {
             if !can_clean_whitespace {
-                return Ok(());
-            }
-            let stripped_content = remove_trailing_whitespace(&current_content);
-            if stripped_content != current_content {
-                current_content = stripped_content;
-                modified = true;
+                return unchanged();
             }
+            Ok(remove_trailing_whitespace(original_content, lines))
         }
         OperationMode::StripNewlines => {
             if !can_clean_newlines {
-                return Ok(());
-            }
-            let stripped_content = collapse_blank_lines(&current_content);
-            if stripped_content != current_content {
-                current_content = stripped_content;
-                modified = true;
+                return unchanged();
             }
+            Ok(collapse_blank_lines(original_content, lines))
         }
         OperationMode::All => {
             if !can_format {
-                return Ok(());
+                return unchanged();
             }
-
-            run_formatter_for_lang(path, lang)?;
-            current_content = fs::read_to_string(path)
-                .map_err(map_err_to_string(path, "Re-read after format 1"))?;
-            if current_content != original_content {
-                modified = true;
+            let content_after_fmt1 = format_content(
+                original_content,
+                path,
+                lang,
+                work_dir,
+                &options.config,
+                lines,
+                metrics,
+            )
+            .map_err(at(Stage::Format))?;
+            if !can_strip {
+                return verify(content_after_fmt1);
             }
-            let content_after_fmt1 = current_content.clone();
+            let lines_after_fmt1 =
+                lines.map(|lines| line_ranges::remap(original_content, &content_after_fmt1, lines));
+            let content_after_strip = strip_comments_smart(
+                &content_after_fmt1,
+                lang,
+                StripSettings {
+                    lines: lines_after_fmt1.as_deref(),
+                    ..strip
+                },
+                CommentSelection::Every,
+                None,
+                metrics,
+            )
+            .map_err(|e| ProcessError::strip(path, lang, e))
+            .map_err(at(Stage::Strip))?;
+            if content_after_strip == content_after_fmt1 {
+                return verify(content_after_fmt1);
+            }
+            let lines_after_strip = lines_after_fmt1
+                .map(|lines| line_ranges::remap(&content_after_fmt1, &content_after_strip, &lines));
+            let content_after_fmt2 = format_content(
+                &content_after_strip,
+                path,
+                lang,
+                work_dir,
+                &options.config,
+                lines_after_strip.as_deref(),
+                metrics,
+            )
+            .map_err(at(Stage::Reformat))?;
+            verify(content_after_fmt2)
+        }
+    }
+}
 
-            if !can_strip {
-                if modified {
-                    fs::write(path, content_after_fmt1).map_err(map_err_to_string(
-                        path,
-                        "Write after format 1 (cannot strip)",
-                    ))?;
-                }
-                return Ok(());
+pub fn process_content(
+    content: &str,
+    path: &Path,
+    mode: OperationMode,
+    options: &ProcessOptions,
+) -> Result<String, ProcessError> {
+    if options.config.is_skipped() {
+        return Ok(content.to_string());
+    }
+    let lang = match language_for_content(path, content, &options.config) {
+        Some(l) => l,
+        None => return Ok(content.to_string()),
+    };
+    let output = transform_content(
+        content,
+        path,
+        lang,
+        &work_dir_for(path),
+        mode,
+        options,
+        &mut FileMetrics::default(),
+    )?;
+    validate_result(path, content, &output)?;
+    Ok(output)
+}
+
+fn process_single_file(
+    path: &Path,
+    mode: OperationMode,
+    options: &ProcessOptions,
+    metrics: &mut FileMetrics,
+) -> Result<FileOutcome, ProcessError> {
+    let lang = match language_for_path(path, &options.config) {
+        Some(l) => l,
+        None => return Ok(FileOutcome::Unchanged),
+    };
+    let staged = if options.index {
+        let staged = StagedFile::open(path)
+            .map_err(|e| ProcessError::from_anyhow(path, "Read staged", &e))?;
+        Some(staged)
+    } else {
+        None
+    };
+    let original_content = match &staged {
+        Some(staged) => staged
+            .content()
+            .map_err(|e| ProcessError::from_anyhow(path, "Read staged", &e))
+            .and_then(|bytes| decode_source(path, bytes))?,
+        None => read_source(path)?,
+    };
+    let cache = options
+        .cache
+        .as_ref()
+        .filter(|_| !options.explain && options.lines.is_none());
+    let cache_key = cache.map(|_| ResultCache::key(&original_content, lang, mode, options));
+    if let (Some(cache), Some(key)) = (cache, &cache_key)
+        && cache.contains(key)
+    {
+        return Ok(FileOutcome::Cached);
+    }
+    let current_content = transform_content(
+        &original_content,
+        path,
+        lang,
+        &work_dir_for(path),
+        mode,
+        options,
+        metrics,
+    )?;
+    commit_result(
+        path,
+        staged.as_ref(),
+        &original_content,
+        &current_content,
+        options,
+        cache_key,
+        metrics,
+    )
+}
+
+fn record_backup(
+    path: &Path,
+    before: &str,
+    after: &str,
+    options: &ProcessOptions,
+) -> Result<(), ProcessError> {
+    let Some(backup) = &options.backup else {
+        return Ok(());
+    };
+    backup
+        .record(path, before, after)
+        .map_err(|e| ProcessError::Backup {
+            path: path.to_path_buf(),
+            message: format!("{:#}", e),
+        })
+}
+
+fn commit_staged(
+    path: &Path,
+    staged: &StagedFile,
+    original_content: &str,
+    current_content: &str,
+    options: &ProcessOptions,
+) -> Result<(), ProcessError> {
+    let stage_error = |e: anyhow::Error| ProcessError::from_anyhow(path, "Update index", &e);
+    let worktree = read_source(path)?;
+    let blob = staged.write_blob(current_content).map_err(stage_error)?;
+    if worktree == original_content {
+        record_backup(path, &worktree, current_content, options)?;
+        staged.stage(&blob).map_err(stage_error)?;
+        return write_atomically(path, current_content, options.config.write.preserve_mtime())
+            .map_err(|e| ProcessError::from_anyhow(path, "Write", &e));
+    }
+    let worktree_error =
+        |e: anyhow::Error| ProcessError::from_anyhow(path, "Update working tree", &e);
+    let patch = staged.worktree_patch(&blob).map_err(stage_error)?;
+    record_backup(path, &worktree, &worktree, options)?;
+    staged
+        .apply_to_worktree(&patch, true)
+        .context("Unstaged changes overlap the reformatted lines; stage or stash them first")
+        .map_err(worktree_error)?;
+    staged.stage(&blob).map_err(stage_error)?;
+    staged
+        .apply_to_worktree(&patch, false)
+        .map_err(worktree_error)?;
+    record_backup(path, &worktree, &read_source(path)?, options)
+}
+
+fn commit_result(
+    path: &Path,
+    staged: Option<&StagedFile>,
+    original_content: &str,
+    current_content: &str,
+    options: &ProcessOptions,
+    cache_key: Option<String>,
+    metrics: &mut FileMetrics,
+) -> Result<FileOutcome, ProcessError> {
+    metrics.bytes_removed = original_content.len() as i64 - current_content.len() as i64;
+    metrics.lines_removed =
+        original_content.lines().count() as i64 - current_content.lines().count() as i64;
+    if current_content == original_content {
+        if let (Some(cache), Some(key)) = (&options.cache, cache_key) {
+            cache.insert(key);
+        }
+        return Ok(FileOutcome::Unchanged);
+    }
+    validate_result(path, original_content, current_content)?;
+    if let Some(staged) = staged {
+        commit_staged(path, staged, original_content, current_content, options)?;
+        return Ok(FileOutcome::Changed);
+    }
+    record_backup(path, original_content, current_content, options)?;
+    write_atomically(path, current_content, options.config.write.preserve_mtime())
+        .map_err(|e| ProcessError::from_anyhow(path, "Write", &e))?;
+    Ok(FileOutcome::Changed)
+}
+
+fn batch_formatter_for(path: &Path, options: &ProcessOptions) -> Option<FormatterSpec> {
+    if options.config.is_skipped() {
+        return None;
+    }
+    let lang = language_for_path(path, &options.config)?;
+    if !can_format(lang, &options.config) {
+        return None;
+    }
+    FormatterRegistry::new(&options.config).batch_formatter(lang)
+}
+
+type BatchOutcome = Option<FileRun>;
+type BatchFile<'a> = (usize, &'a Path, &'a ProcessOptions);
+type ResolvedFile = (PathBuf, OperationMode, Result<ProcessOptions, ProcessError>);
+
+fn format_batch(spec: &FormatterSpec, chunk: &[BatchFile]) -> Vec<(usize, BatchOutcome)> {
+    let started = Instant::now();
+    let mut outcomes = Vec::new();
+    let mut staged = Vec::new();
+    for &(index, path, options) in chunk {
+        let staging = || -> Result<Option<_>, ProcessError> {
+            let lang = language_for_path(path, &options.config).unwrap_or_default();
+            let original = read_source(path)?;
+            let cache = options.cache.as_ref().filter(|_| !options.explain);
+            let key =
+                cache.map(|_| ResultCache::key(&original, lang, OperationMode::Format, options));
+            if let (Some(cache), Some(key)) = (cache, &key)
+                && cache.contains(key)
+            {
+                return Ok(None);
             }
-            let stripped_content = strip_comments_smart(&content_after_fmt1, lang)
-                .map_err(|e| format!("Smart stripping failed for --all: {}", e))?;
-            if stripped_content != content_after_fmt1 {
-                current_content = stripped_content;
-                modified = true;
+            let temp = format_temp_file(&original, path, &work_dir_for(path))?;
+            Ok(Some((original, key, temp)))
+        };
+        match staging() {
+            Ok(Some((original, key, temp))) => {
+                staged.push((index, path, options, original, key, temp))
             }
-            let content_after_strip = current_content.clone();
-
-            if modified && can_format {
-                let parent_dir = path
-                    .parent()
-                    .ok_or_else(|| format!("Failed to get parent dir for {}", path.display()))?;
-                let suffix = path
-                    .extension()
-                    .map(|s| format!(".{}", s.to_string_lossy()))
-                    .unwrap_or_default();
-                let mut temp_file = tempfile::Builder::new()
-                    .prefix(".xzen_fmt2_")
-                    .suffix(&suffix)
-                    .tempfile_in(parent_dir)
-                    .map_err(map_err_to_string(path, "Create format 2 temp file"))?;
-                temp_file
-                    .write_all(content_after_strip.as_bytes())
-                    .map_err(map_err_to_string(path, "Write format 2 temp file"))?;
-
-                let temp_path_obj = temp_file.into_temp_path();
-                run_formatter_for_lang(&temp_path_obj, lang)
-                    .map_err(|e| format!("Final format failed for --all: {}", e))?;
-                current_content = fs::read_to_string(&temp_path_obj)
-                    .map_err(map_err_to_string(path, "Read temp file after final format"))?;
-                drop(temp_path_obj);
-
-                if current_content != content_after_strip {
-                    modified = true;
-                }
+            Ok(None) => outcomes.push((index, Some(FileRun::early(Ok(FileOutcome::Cached))))),
+            Err(e) => outcomes.push((index, Some(FileRun::early(Err(e))))),
+        }
+    }
+    let temps: Vec<&Path> = staged.iter().map(|s| s.5.as_ref()).collect();
+    let result = spec.run_batch(&temps);
+    let duration = started.elapsed() / staged.len().max(1) as u32;
+    match result {
+        Ok(()) => {
+            for (index, path, options, original, key, temp) in &staged {
+                let mut metrics = FileMetrics {
+                    formatters: vec![spec.name.clone()],
+                    ..FileMetrics::default()
+                };
+                let outcome = read_formatted(temp, path).and_then(|current| {
+                    commit_result(
+                        path,
+                        None,
+                        original,
+                        &current,
+                        options,
+                        key.clone(),
+                        &mut metrics,
+                    )
+                });
+                let run = FileRun {
+                    outcome,
+                    metrics,
+                    duration,
+                };
+                outcomes.push((*index, Some(run)));
+            }
+        }
+        Err(e) => {
+            let (exit_code, message) = match &e {
+                ToolError::Rejected { code, stderr, .. } => (*code, stderr.clone()),
+                _ => (None, String::new()),
+            };
+            for (index, path, options, _, _, temp) in &staged {
+                let temp_name = temp.to_string_lossy();
+                let lines: Vec<String> = message
+                    .lines()
+                    .filter(|line| line.contains(temp_name.as_ref()))
+                    .map(|line| line.replace(temp_name.as_ref(), &path.to_string_lossy()))
+                    .collect();
+                let run = (!lines.is_empty()).then(|| FileRun {
+                    outcome: Err(ProcessError::FormatterFailed {
+                        path: path.to_path_buf(),
+                        language: language_for_path(path, &options.config)
+                            .unwrap_or_default()
+                            .to_string(),
+                        tool: spec.name.clone(),
+                        exit_code,
+                        stderr: lines.join("\n"),
+                    }),
+                    metrics: FileMetrics {
+                        formatters: vec![spec.name.clone()],
+                        ..FileMetrics::default()
+                    },
+                    duration,
+                });
+                outcomes.push((*index, run));
             }
         }
     }
+    outcomes
+}
 
-    if modified {
-        fs::write(path, current_content).map_err(map_err_to_string(path, "Write final result"))?;
-    }
-    Ok(())
-}
-
-fn run_formatter_for_lang(p: &Path, l: &str) -> Result<(), String> {
-    match l {
-        "rust" => run_rustfmt(p),
-        "c" => run_astyle(p, "c"),
-        "cpp" => run_astyle(p, "cpp"),
-        "ruby" => run_rubocop_autocorrect(p),
-        "toml" => run_taplo_fmt(p),
-        "json" | "yaml" | "css" | "scss" | "less" | "markdown" | "javascript" | "typescript" => {
-            run_prettierd_fmt(p)
-        }
-        "python" => run_black(p),
-        "go" => run_gofmt(p),
-        "lua" => run_stylua(p),
-        "shell" | "bash" => run_shfmt(p),
-        "fish" => run_fish_indent(p),
-        "perl" => run_perltidy(p),
-        "haskell" => run_ormolu(p),
-        "cabal" => run_cabal_fmt(p),
-        "elm" => run_elm_format(p),
-        "crystal" => run_crystal_format(p),
-        "java" => run_google_java_format(p),
-        "kotlin" => run_ktlint(p),
-        "swift" => run_swift_format(p),
-        "html" | "xml" => run_tidy(p),
-        "nix" => run_nixfmt(p),
-        "twig" => run_djlint(p),
-        "conf" => run_nginxfmt(p),
-        "dockerfile" => run_dockfmt(p),
-        "assembly" => run_asmfmt(p),
-        _ => Ok(()),
-    }
-    .map_err(|e| e.to_string())
-}
-
-pub fn process_files(files: Vec<PathBuf>, mode: OperationMode) -> Result<Vec<ProcessedFileResult>> {
-    let r: Vec<ProcessedFileResult> = files
-        .par_iter()
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct BatchKey {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    success_codes: Vec<i32>,
+    timeout_secs: u64,
+    batch: usize,
+}
+
+impl BatchKey {
+    fn new(spec: &FormatterSpec) -> Self {
+        BatchKey {
+            name: spec.name.clone(),
+            command: spec.command.clone(),
+            args: spec.args.clone(),
+            success_codes: spec.success_codes.clone(),
+            timeout_secs: spec.timeout_secs,
+            batch: spec.batch,
+        }
+    }
+}
+
+fn process_batches(resolved: &[ResolvedFile]) -> Vec<(usize, BatchOutcome)> {
+    let mut groups: HashMap<BatchKey, (FormatterSpec, Vec<BatchFile>)> = HashMap::new();
+    for (index, (path, mode, file_options)) in resolved.iter().enumerate() {
+        let Ok(file_options) = file_options else {
+            continue;
+        };
+        if *mode != OperationMode::Format {
+            continue;
+        }
+        if let Some(spec) = batch_formatter_for(path, file_options) {
+            groups
+                .entry(BatchKey::new(&spec))
+                .or_insert_with(|| (spec, Vec::new()))
+                .1
+                .push((index, path, file_options));
+        }
+    }
+    let jobs: Vec<(&FormatterSpec, &[BatchFile])> = groups
+        .values()
+        .flat_map(|(spec, files)| files.chunks(spec.batch).map(move |chunk| (spec, chunk)))
+        .filter(|(_, chunk)| chunk.len() > 1)
+        .collect();
+    jobs.par_iter()
+        .flat_map_iter(|(spec, chunk)| format_batch(spec, chunk))
+        .collect()
+}
+
+/// Processes `files` in `mode`, or in the mode configured for each file's
+/// directory when no mode was given on the command line.
+pub fn process_files(
+    files: Vec<PathBuf>,
+    mode: Option<OperationMode>,
+    options: &ProcessOptions,
+) -> Result<Vec<ProcessedFileResult>> {
+    let mut resolver = ConfigResolver::new(&options.config);
+    let resolved: Vec<ResolvedFile> = files
+        .into_iter()
         .map(|p| {
-            let o = process_single_file(p, mode);
+            let o = resolver
+                .resolve(&p)
+                .map(|config| ProcessOptions {
+                    config,
+                    ..options.clone()
+                })
+                .map_err(|e| ProcessError::Config {
+                    path: p.clone(),
+                    message: format!("{:#}", e),
+                });
+            let file_mode = mode
+                .or_else(|| o.as_ref().ok().and_then(|o| o.config.mode))
+                .unwrap_or(OperationMode::Format);
+            (p, file_mode, o)
+        })
+        .collect();
+    let mut batched: Vec<BatchOutcome> = resolved.iter().map(|_| None).collect();
+    if !options.verify && !options.index && options.lines.is_none() {
+        for (index, outcome) in process_batches(&resolved) {
+            batched[index] = outcome;
+        }
+    }
+    let r: Vec<ProcessedFileResult> = resolved
+        .par_iter()
+        .zip(batched)
+        .map(|((p, mode, file_options), batched)| {
+            let mode = *mode;
+            let run = batched.unwrap_or_else(|| {
+                let started = Instant::now();
+                let mut metrics = FileMetrics::default();
+                let outcome = match file_options {
+                    Ok(file_options) if file_options.config.is_skipped() => {
+                        Ok(FileOutcome::Unchanged)
+                    }
+                    Ok(file_options) => process_single_file(p, mode, file_options, &mut metrics),
+                    Err(e) => Err(e.clone()),
+                };
+                FileRun {
+                    outcome,
+                    metrics,
+                    duration: started.elapsed(),
+                }
+            });
             ProcessedFileResult {
                 path: p.clone(),
-                error: o.err(),
+                language: file_options
+                    .as_ref()
+                    .ok()
+                    .and_then(|o| language_for_path(p, &o.config))
+                    .map(str::to_string),
+                mode,
+                changed: run.outcome == Ok(FileOutcome::Changed),
+                cached: run.outcome == Ok(FileOutcome::Cached),
+                metrics: run.metrics,
+                duration: run.duration,
+                error: run.outcome.err(),
             }
         })
         .collect();
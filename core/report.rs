@@ -0,0 +1,206 @@
+use crate::processor::{OperationMode, ProcessedFileResult};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Jsonl,
+    Sarif,
+    Junit,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    pub files: usize,
+    pub changed: usize,
+    pub cached: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    pub duration_ms: f64,
+}
+
+impl RunSummary {
+    pub fn from_results(results: &[ProcessedFileResult]) -> Self {
+        let mut summary = RunSummary {
+            files: results.len(),
+            ..RunSummary::default()
+        };
+        for result in results {
+            summary.changed += usize::from(result.changed);
+            summary.cached += usize::from(result.cached);
+            match &result.error {
+                Some(e) if e.is_timeout() => summary.timed_out += 1,
+                Some(_) => summary.failed += 1,
+                None => {}
+            }
+            summary.duration_ms += result.duration.as_secs_f64() * 1000.0;
+        }
+        summary
+    }
+}
+
+fn display_path(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    text.strip_prefix("./").unwrap_or(&text).to_string()
+}
+
+fn render_json(results: &[ProcessedFileResult], mode: OperationMode) -> Result<String> {
+    let report = json!({
+        "tool": "xzenfmt",
+        "version": env!("CARGO_PKG_VERSION"),
+        "mode": mode,
+        "summary": RunSummary::from_results(results),
+        "files": results,
+    });
+    serde_json::to_string_pretty(&report).context("Serialize report")
+}
+
+fn render_jsonl(results: &[ProcessedFileResult]) -> Result<String> {
+    let lines = results
+        .iter()
+        .map(|result| serde_json::to_string(result).context("Serialize result"))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(lines.join("\n"))
+}
+
+fn render_sarif(results: &[ProcessedFileResult], mode: OperationMode) -> Result<String> {
+    let findings: Vec<serde_json::Value> = results
+        .iter()
+        .filter_map(|result| {
+            let (rule, level, message) = match &result.error {
+                Some(e) => (e.kind(), "error", e.to_string()),
+                None if result.changed => (
+                    "changed",
+                    "note",
+                    format!("File was rewritten by xzenfmt ({:?})", mode),
+                ),
+                None => return None,
+            };
+            Some(json!({
+                "ruleId": rule,
+                "level": level,
+                "message": { "text": message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": display_path(&result.path) }
+                    }
+                }],
+            }))
+        })
+        .collect();
+    let rule = |id: &str, text: &str| json!({ "id": id, "shortDescription": { "text": text } });
+    let report = json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "xzenfmt",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [
+                        rule("permission-denied", "The file could not be read or written"),
+                        rule("io", "An I/O error occurred while processing the file"),
+                        rule("not-utf8", "The file is not valid UTF-8"),
+                        rule("formatter-missing", "No formatter for the language is installed"),
+                        rule("formatter-failed", "The formatter rejected the file"),
+                        rule("timed-out", "A formatter exceeded its timeout"),
+                        rule("not-idempotent", "A second formatter run changed the result again"),
+                        rule("strip", "Comment stripping failed"),
+                        rule("invalid-output", "The result was refused before writing"),
+                        rule("backup", "The original could not be backed up"),
+                        rule("config", "The configuration for the file is invalid"),
+                        rule("changed", "The file was rewritten"),
+                    ],
+                }
+            },
+            "results": findings,
+        }],
+    });
+    serde_json::to_string_pretty(&report).context("Serialize SARIF report")
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if c.is_control() && !matches!(c, '\n' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn render_junit(results: &[ProcessedFileResult], mode: OperationMode) -> String {
+    let summary = RunSummary::from_results(results);
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"xzenfmt\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        summary.files,
+        summary.failed,
+        summary.timed_out,
+        summary.duration_ms / 1000.0
+    ));
+    out.push_str(&format!(
+        "  <testsuite name=\"xzenfmt {:?}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+        mode,
+        summary.files,
+        summary.failed,
+        summary.timed_out,
+        summary.duration_ms / 1000.0
+    ));
+    for result in results {
+        out.push_str(&format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            xml_escape(result.language.as_deref().unwrap_or("unknown")),
+            xml_escape(&display_path(&result.path)),
+            result.duration.as_secs_f64()
+        ));
+        match &result.error {
+            Some(e) => {
+                let tag = if e.is_timeout() { "error" } else { "failure" };
+                let message = e.to_string();
+                out.push_str(&format!(
+                    ">\n      <{} type=\"{}\" message=\"{}\">{}</{}>\n    </testcase>\n",
+                    tag,
+                    e.kind(),
+                    xml_escape(message.lines().next().unwrap_or_default()),
+                    xml_escape(&message),
+                    tag
+                ));
+            }
+            None => out.push_str("/>\n"),
+        }
+    }
+    out.push_str("  </testsuite>\n</testsuites>");
+    out
+}
+
+pub fn render_report(
+    results: &[ProcessedFileResult],
+    mode: OperationMode,
+    format: OutputFormat,
+) -> Result<String> {
+    let mut out = match format {
+        OutputFormat::Text => anyhow::bail!("The text report is printed by the CLI"),
+        OutputFormat::Json => render_json(results, mode)?,
+        OutputFormat::Jsonl => render_jsonl(results)?,
+        OutputFormat::Sarif => render_sarif(results, mode)?,
+        OutputFormat::Junit => render_junit(results, mode),
+    };
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
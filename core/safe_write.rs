@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub fn validate_output(original: &str, output: &str) -> Result<()> {
+    if output.trim().is_empty() && !original.trim().is_empty() {
+        anyhow::bail!("Refusing to write an empty result over a non-empty file");
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn preserve_ownership(temp: &File, metadata: &fs::Metadata) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let current = temp.metadata()?;
+    if current.uid() == metadata.uid() && current.gid() == metadata.gid() {
+        return Ok(());
+    }
+    std::os::unix::fs::fchown(temp, Some(metadata.uid()), Some(metadata.gid()))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn preserve_ownership(_temp: &File, _metadata: &fs::Metadata) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_hard_linked(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_hard_linked(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Renaming over a hard-linked file would split it from its other names, so
+/// those are rewritten in place instead. The new content is first written and
+/// synced to a temp file next to the target; if the rewrite fails or the
+/// process dies halfway, that copy is left behind to recover from.
+fn write_in_place(target: &Path, content: &str, modified: Option<SystemTime>) -> Result<()> {
+    let dir = target
+        .parent()
+        .with_context(|| format!("No parent directory for {}", target.display()))?;
+    let mut copy = tempfile::Builder::new()
+        .prefix(".xzen_write_")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create temp file in {}", dir.display()))?;
+    copy.write_all(content.as_bytes())
+        .context("Failed to write temp file")?;
+    copy.as_file()
+        .sync_all()
+        .context("Failed to sync temp file")?;
+    let rewrite = || -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(target)
+            .with_context(|| format!("Failed to open {}", target.display()))?;
+        file.write_all(content.as_bytes())
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+        file.set_len(content.len() as u64)
+            .with_context(|| format!("Failed to truncate {}", target.display()))?;
+        if let Some(modified) = modified {
+            file.set_modified(modified)
+                .context("Failed to preserve modification time")?;
+        }
+        file.sync_all()
+            .with_context(|| format!("Failed to sync {}", target.display()))
+    };
+    rewrite().map_err(|e| match copy.keep() {
+        Ok((_, kept)) => e.context(format!("The new content was kept in {}", kept.display())),
+        Err(_) => e,
+    })
+}
+
+pub fn write_atomically(path: &Path, content: &str, preserve_mtime: bool) -> Result<()> {
+    let target =
+        fs::canonicalize(path).with_context(|| format!("Failed to resolve {}", path.display()))?;
+    let metadata = fs::metadata(&target)
+        .with_context(|| format!("Failed to read metadata of {}", target.display()))?;
+    let modified = if preserve_mtime {
+        Some(metadata.modified()?)
+    } else {
+        None
+    };
+    if is_hard_linked(&metadata) {
+        return write_in_place(&target, content, modified);
+    }
+    let dir = target
+        .parent()
+        .with_context(|| format!("No parent directory for {}", target.display()))?;
+    let mut temp = tempfile::Builder::new()
+        .prefix(".xzen_write_")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create temp file in {}", dir.display()))?;
+    temp.write_all(content.as_bytes())
+        .context("Failed to write temp file")?;
+    let file = temp.as_file();
+    file.set_permissions(metadata.permissions())
+        .context("Failed to copy permissions")?;
+    if let Err(e) = preserve_ownership(file, &metadata) {
+        eprintln!(
+            "Warning: Cannot preserve ownership of {}: {:#}",
+            target.display(),
+            e
+        );
+    }
+    if let Some(modified) = modified {
+        file.set_modified(modified)
+            .context("Failed to preserve modification time")?;
+    }
+    file.sync_all().context("Failed to sync temp file")?;
+    temp.persist(&target)
+        .with_context(|| format!("Failed to replace {}", target.display()))?;
+    if let Ok(dir_handle) = File::open(dir) {
+        let _ = dir_handle.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_links_are_rewritten_in_place_without_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.rs");
+        let link = dir.path().join("b.rs");
+        fs::write(&file, "old content that is longer\n").unwrap();
+        fs::hard_link(&file, &link).unwrap();
+        write_atomically(&file, "new\n", false).unwrap();
+        assert_eq!(fs::read_to_string(&link).unwrap(), "new\n");
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.rs", "b.rs"]);
+    }
+}
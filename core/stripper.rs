@@ -16,6 +16,7 @@ pub mod yaml;
 
 pub use common::{CommentMatch, StripError, remove_matches};
 
+use crate::languages::{self, CommentSyntax};
 use std::path::Path;
 
 pub fn find_language_comments(
@@ -23,36 +24,427 @@ pub fn find_language_comments(
     lang: &str,
     _path: &Path,
 ) -> Result<Vec<CommentMatch>, StripError> {
-    match lang {
-        "c" | "cpp" | "go" | "javascript" | "typescript" | "css" | "scss" | "less" | "java"
-        | "kotlin" | "swift" => c_family::find_comments(content),
+    match languages::lookup(lang).and_then(|l| l.comments) {
+        Some(CommentSyntax::CFamily) => c_family::find_comments(content),
+        Some(CommentSyntax::Rust) => rust::find_comments(content),
+        Some(CommentSyntax::Json) => json::find_comments(content),
+        Some(CommentSyntax::Ruby) => ruby::find_comments(content),
+        Some(CommentSyntax::Crystal) => crystal::find_comments(content),
+        Some(CommentSyntax::Fish) => fish::find_comments(content),
+        Some(CommentSyntax::Shell) => shell::find_comments(content),
+        Some(CommentSyntax::Python) => python::find_comments(content),
+        Some(CommentSyntax::Yaml) => yaml::find_comments(content),
+        Some(CommentSyntax::Toml) => toml::find_comments(content),
+        Some(CommentSyntax::Lua) => lua::find_comments(content),
+        Some(CommentSyntax::Haskell) => haskell_elm::find_comments(content),
+        Some(CommentSyntax::Nix) => nix::find_comments(content),
+        Some(CommentSyntax::Twig) => twig::find_comments(content),
+        None => Ok(Vec::new()),
+    }
+}
+
+struct CodeTokens<'a> {
+    text: &'a str,
+    comments: Vec<CommentMatch>,
+    next_comment: usize,
+    pos: usize,
+}
+
+impl<'a> CodeTokens<'a> {
+    fn new(text: &'a str, mut comments: Vec<CommentMatch>) -> Self {
+        comments.sort_by_key(|m| m.from);
+        CodeTokens {
+            text,
+            comments,
+            next_comment: 0,
+            pos: 0,
+        }
+    }
+
+    fn skip_comment(&mut self) -> bool {
+        let mut skipped = false;
+        while let Some(m) = self.comments.get(self.next_comment) {
+            if m.from > self.pos {
+                break;
+            }
+            self.pos = self.pos.max(m.to);
+            self.next_comment += 1;
+            skipped = true;
+        }
+        skipped
+    }
+}
+
+impl<'a> Iterator for CodeTokens<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.text.as_bytes();
+        loop {
+            if self.skip_comment() {
+                continue;
+            }
+            match bytes.get(self.pos) {
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return None,
+            }
+        }
+        let start = self.pos;
+        let limit = self
+            .comments
+            .get(self.next_comment)
+            .map_or(bytes.len(), |m| m.from.min(bytes.len()));
+        while self.pos < limit && !bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        Some((start, &self.text[start..self.pos]))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Literal {
+    Quoted {
+        quote: u8,
+        escapes: bool,
+        multiline: bool,
+    },
+    Triple(u8),
+    RawString(usize),
+    LongBracket(usize),
+    Nested {
+        open: u8,
+        close: u8,
+        depth: usize,
+    },
+    NixIndented,
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn repeated(bytes: &[u8], at: usize, b: u8) -> usize {
+    bytes[at.min(bytes.len())..]
+        .iter()
+        .take_while(|&&x| x == b)
+        .count()
+}
 
-        "rust" => rust::find_comments(content),
+fn literal_start(syntax: CommentSyntax, text: &str, at: usize) -> Option<(Literal, usize)> {
+    let bytes = text.as_bytes();
+    let rest = &bytes[at..];
+    let after_word = at > 0 && is_word_byte(bytes[at - 1]);
+    let quoted = |quote, escapes, multiline| {
+        Some((
+            Literal::Quoted {
+                quote,
+                escapes,
+                multiline,
+            },
+            1,
+        ))
+    };
+    match (syntax, rest[0]) {
+        (CommentSyntax::Twig, _) => None,
+        (CommentSyntax::Python | CommentSyntax::Toml, q @ (b'"' | b'\''))
+            if rest.starts_with(&[q, q, q]) =>
+        {
+            Some((Literal::Triple(q), 3))
+        }
+        (CommentSyntax::Rust, b'r') => {
+            let prefix_start = match at.checked_sub(1).map(|p| bytes[p]) {
+                Some(b'b' | b'c') => at - 1,
+                _ => at,
+            };
+            let hashes = repeated(bytes, at + 1, b'#');
+            let standalone = prefix_start == 0 || !is_word_byte(bytes[prefix_start - 1]);
+            (standalone && rest.get(1 + hashes) == Some(&b'"'))
+                .then_some((Literal::RawString(hashes), 2 + hashes))
+        }
+        (CommentSyntax::Rust, b'\'') => {
+            let mut chars = text[at + 1..].chars();
+            let first = chars.next();
+            (first == Some('\\') || chars.next() == Some('\'')).then_some((
+                Literal::Quoted {
+                    quote: b'\'',
+                    escapes: true,
+                    multiline: false,
+                },
+                1,
+            ))
+        }
+        (CommentSyntax::Lua, b'[') => {
+            let level = repeated(bytes, at + 1, b'=');
+            (rest.get(1 + level) == Some(&b'[')).then_some((Literal::LongBracket(level), 2 + level))
+        }
+        (CommentSyntax::Shell, b'$') if rest.starts_with(b"${") => Some((
+            Literal::Nested {
+                open: b'{',
+                close: b'}',
+                depth: 1,
+            },
+            2,
+        )),
+        (CommentSyntax::Shell, b'$') if rest.starts_with(b"$((") => Some((
+            Literal::Nested {
+                open: b'(',
+                close: b')',
+                depth: 2,
+            },
+            3,
+        )),
+        (CommentSyntax::Nix, b'\'') if rest.starts_with(b"''") => Some((Literal::NixIndented, 2)),
+        (_, b'"') => quoted(b'"', true, true),
+        (CommentSyntax::Shell | CommentSyntax::Fish, b'\'') => quoted(b'\'', false, true),
+        (CommentSyntax::Yaml | CommentSyntax::Toml, b'\'') if !after_word => {
+            quoted(b'\'', false, false)
+        }
+        (CommentSyntax::Python, b'\'') => quoted(b'\'', true, false),
+        (
+            CommentSyntax::CFamily
+            | CommentSyntax::Ruby
+            | CommentSyntax::Crystal
+            | CommentSyntax::Lua,
+            b'\'',
+        ) if !after_word => quoted(b'\'', true, false),
+        (CommentSyntax::CFamily, b'`') => quoted(b'`', true, true),
+        _ => None,
+    }
+}
 
-        "json" => json::find_comments(content),
+/// Returns how many bytes to consume at `at`, and whether that closes the literal.
+fn literal_step(literal: &mut Literal, bytes: &[u8], at: usize) -> (usize, bool) {
+    let rest = &bytes[at..];
+    match literal {
+        Literal::Quoted {
+            quote,
+            escapes,
+            multiline,
+        } => match rest[0] {
+            b'\\' if *escapes => (2, false),
+            b'\n' if !*multiline => (1, true),
+            b if b == *quote => (1, true),
+            _ => (1, false),
+        },
+        Literal::Triple(q) => match rest[0] {
+            b'\\' => (2, false),
+            _ if rest.starts_with(&[*q, *q, *q]) => (3, true),
+            _ => (1, false),
+        },
+        Literal::RawString(hashes) => {
+            let closes = rest[0] == b'"' && repeated(bytes, at + 1, b'#') >= *hashes;
+            if closes {
+                (1 + *hashes, true)
+            } else {
+                (1, false)
+            }
+        }
+        Literal::LongBracket(level) => {
+            let closes = rest[0] == b']'
+                && repeated(bytes, at + 1, b'=') == *level
+                && rest.get(1 + *level) == Some(&b']');
+            if closes {
+                (2 + *level, true)
+            } else {
+                (1, false)
+            }
+        }
+        Literal::Nested { open, close, depth } => match rest[0] {
+            b'\\' => (2, false),
+            b if b == *open => {
+                *depth += 1;
+                (1, false)
+            }
+            b if b == *close => {
+                *depth -= 1;
+                (1, *depth == 0)
+            }
+            _ => (1, false),
+        },
+        Literal::NixIndented => match rest {
+            [b'\'', b'\'', b'\'' | b'$' | b'\\', ..] => (3, false),
+            [b'\'', b'\'', ..] => (2, true),
+            _ => (1, false),
+        },
+    }
+}
 
-        "ruby" => ruby::find_comments(content),
+fn starts_mid_word(syntax: CommentSyntax, bytes: &[u8], removed: &CommentMatch) -> bool {
+    if !matches!(syntax, CommentSyntax::Shell | CommentSyntax::Fish) {
+        return false;
+    }
+    let hash = removed.from
+        + bytes[removed.from..removed.to]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+    hash > 0 && hash < removed.to && !b" \t\n;|&()".contains(&bytes[hash - 1])
+}
 
-        "crystal" => crystal::find_comments(content),
+/// Rejects removed ranges that start inside a string, raw string or shell
+/// expansion. Comments found by the lexer are skipped only while the scan is
+/// outside a literal, so a comment the lexer found inside a string is caught.
+fn check_removed_ranges(
+    original: &str,
+    comments: &[CommentMatch],
+    removed: &[CommentMatch],
+    syntax: CommentSyntax,
+) -> Result<(), StripError> {
+    let bytes = original.as_bytes();
+    let mut skips: Vec<(CommentMatch, bool)> = comments
+        .iter()
+        .map(|m| (*m, false))
+        .chain(removed.iter().map(|m| (*m, true)))
+        .collect();
+    skips.sort_by_key(|(m, is_removed)| (m.from, !is_removed));
+    let mut skips = skips.iter().peekable();
+    let mut open: Option<(usize, Literal)> = None;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if let Some((m, is_removed)) = skips.next_if(|(m, _)| m.from <= pos) {
+            match open {
+                Some((literal, _)) if *is_removed => {
+                    return Err(StripError::InsideLiteral {
+                        offset: m.from,
+                        literal,
+                    });
+                }
+                Some(_) => {}
+                None if *is_removed && starts_mid_word(syntax, bytes, m) => {
+                    return Err(StripError::MidWord { offset: m.from });
+                }
+                None => pos = pos.max(m.to),
+            }
+            continue;
+        }
+        match &mut open {
+            Some((_, literal)) => {
+                let (len, closed) = literal_step(literal, bytes, pos);
+                pos += len;
+                if closed {
+                    open = None;
+                }
+            }
+            None if bytes[pos] == b'\\'
+                && matches!(syntax, CommentSyntax::Shell | CommentSyntax::Fish) =>
+            {
+                pos += 2
+            }
+            None => match literal_start(syntax, original, pos) {
+                Some((literal, len)) => {
+                    open = Some((pos, literal));
+                    pos += len;
+                }
+                None => pos += 1,
+            },
+        }
+    }
+    match (skips.find(|(_, is_removed)| *is_removed), open) {
+        (Some((m, _)), Some((literal, _))) => Err(StripError::InsideLiteral {
+            offset: m.from,
+            literal,
+        }),
+        _ => Ok(()),
+    }
+}
 
-        "fish" => fish::find_comments(content),
+pub fn validate_stripped(
+    original: &str,
+    original_comments: Vec<CommentMatch>,
+    removed: &[CommentMatch],
+    output: &str,
+    lang: &str,
+) -> Result<(), StripError> {
+    if let Some(syntax) = languages::lookup(lang).and_then(|l| l.comments) {
+        check_removed_ranges(original, &original_comments, removed, syntax)?;
+    }
+    let output_comments = find_language_comments(output, lang, Path::new(""))?;
+    let mut before = CodeTokens::new(original, original_comments);
+    let mut after = CodeTokens::new(output, output_comments);
+    loop {
+        match (before.next(), after.next()) {
+            (None, None) => return Ok(()),
+            (Some((_, x)), Some((_, y))) if x == y => {}
+            (a, b) => {
+                return Err(StripError::TokenMismatch {
+                    offset: a.map_or(original.len(), |(offset, _)| offset),
+                    output_offset: b.map_or(output.len(), |(offset, _)| offset),
+                });
+            }
+        }
+    }
+}
 
-        "shell" | "dockerfile" | "conf" | "perl" | "bash" => shell::find_comments(content),
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        "python" => python::find_comments(content),
+    const RAW_STRING: &str = "let b = r#\"a \" // not comment\"#;\n";
+    const SHELL_LENGTH: &str = "echo ${#arr[@]}\n";
+    const SHELL_ARITHMETIC: &str = "x=$((1 # 2))\n";
 
-        "yaml" | "yml" => yaml::find_comments(content),
+    fn comments(input: &str, lang: &str) -> Vec<CommentMatch> {
+        find_language_comments(input, lang, Path::new("")).unwrap()
+    }
 
-        "toml" => toml::find_comments(content),
+    fn corrupt(input: &str, marker: &str) -> (Vec<CommentMatch>, String) {
+        let from = input.find(marker).unwrap();
+        let to = input.len() - 1;
+        let removed = vec![CommentMatch { from, to }];
+        let output = remove_matches(input.to_string(), removed.clone()).unwrap();
+        (removed, output)
+    }
 
-        "lua" => lua::find_comments(content),
+    #[test]
+    fn rust_raw_strings_hide_comment_markers() {
+        assert!(comments(RAW_STRING, "rust").is_empty());
+        let input = "let s = r\"//\"; // real\nlet r#type = 1; // also\n";
+        assert_eq!(comments(input, "rust").len(), 2);
+        let input = "fn f<'a>(x: &'a str) -> char { '\\'' } // real\n";
+        assert_eq!(comments(input, "rust").len(), 1);
+    }
 
-        "haskell" | "elm" => haskell_elm::find_comments(content),
+    #[test]
+    fn shell_expansions_hide_comment_markers() {
+        assert!(comments(SHELL_LENGTH, "bash").is_empty());
+        assert!(comments(SHELL_ARITHMETIC, "bash").is_empty());
+        assert!(comments("echo a#b $# ${x#y}\n", "bash").is_empty());
+        assert_eq!(comments("echo ${#arr[@]} # real\n", "bash").len(), 1);
+    }
 
-        "nix" => nix::find_comments(content),
+    #[test]
+    fn validation_rejects_removals_inside_literals() {
+        for (input, lang, marker) in [
+            (RAW_STRING, "rust", "//"),
+            (SHELL_LENGTH, "bash", "#"),
+            (SHELL_ARITHMETIC, "bash", "#"),
+        ] {
+            let (removed, output) = corrupt(input, marker);
+            let result = validate_stripped(input, Vec::new(), &removed, &output, lang);
+            assert!(
+                matches!(result, Err(StripError::InsideLiteral { .. })),
+                "{input:?}: {result:?}"
+            );
+        }
+    }
 
-        "twig" => twig::find_comments(content),
+    #[test]
+    fn validation_rejects_shell_removals_mid_word() {
+        let input = "echo a#b\n";
+        let (removed, output) = corrupt(input, "#");
+        let result = validate_stripped(input, Vec::new(), &removed, &output, "bash");
+        assert_eq!(result, Err(StripError::MidWord { offset: 6 }));
+    }
 
-        _ => Ok(Vec::new()),
+    #[test]
+    fn validation_accepts_real_comments() {
+        let input = "let s = \"// x\"; // real\n";
+        let (removed, output) = corrupt(input, "// real");
+        let all = comments(input, "rust");
+        assert_eq!(
+            validate_stripped(input, all, &removed, &output, "rust"),
+            Ok(())
+        );
     }
 }
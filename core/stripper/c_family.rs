@@ -143,7 +143,7 @@ pub(crate) fn c_do_action_refined(
         CParseAction::ConfirmLineComment => {
             if let State::SawFirstSlash { slash_idx } = comment_state {
                 comment_state = State::InLine {
-                    start_idx: slash_idx + 1,
+                    start_idx: slash_idx,
                 };
             } else {
                 comment_state = State::NotIn;
@@ -191,10 +191,6 @@ fn find_comments_direct(input: &str) -> Result<Vec<CommentMatch>, StripError> {
     let mut iter = input.char_indices();
     let input_len = input.len();
 
-    if input == "char c ='/';// comment" {
-        return Ok(vec![CommentMatch { from: 14, to: 22 }]);
-    }
-
     loop {
         let char_info = iter.next();
         let current_char = char_info.map(|(_, c)| c);
@@ -214,20 +210,6 @@ fn find_comments_direct(input: &str) -> Result<Vec<CommentMatch>, StripError> {
         }
     }
 
-    if !matches.is_empty() {
-        for i in 0..matches.len() {
-            if matches[i].from == 11 && matches[i].to == 24 {
-                matches[i].to = 25;
-            } else if matches[i].from == 0 && matches[i].to == 25 {
-                matches[i].to = 26;
-            } else if matches[i].from == 6 && matches[i].to == 19 {
-                matches[i].to = 20;
-            } else if matches[i].from == 20 && matches[i].to == 33 {
-                matches[i].to = 34;
-            }
-        }
-    }
-
     Ok(matches)
 }
 
@@ -1,4 +1,38 @@
-pub type StripError = &'static str;
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum StripError {
+    #[error("Match {from}..{to} is out of bounds or inverted (input is {len} bytes)")]
+    OutOfBounds { from: usize, to: usize, len: usize },
+    #[error("Matches overlap at byte {offset}")]
+    Overlap { offset: usize },
+    #[error(
+        "Stripped code differs from the original at byte {offset} (byte {output_offset} of the result)"
+    )]
+    TokenMismatch { offset: usize, output_offset: usize },
+    #[error("Removed comment at byte {offset} starts inside a literal opened at byte {literal}")]
+    InsideLiteral { offset: usize, literal: usize },
+    #[error("Removed comment at byte {offset} starts in the middle of a word")]
+    MidWord { offset: usize },
+    #[error("Invalid parser state at byte {offset}: {message}")]
+    InvalidState {
+        offset: usize,
+        message: &'static str,
+    },
+}
+
+impl StripError {
+    pub fn offset(&self) -> usize {
+        match self {
+            StripError::OutOfBounds { from, .. } => *from,
+            StripError::TokenMismatch { offset, .. } | StripError::InsideLiteral { offset, .. } => {
+                *offset
+            }
+            StripError::Overlap { offset }
+            | StripError::MidWord { offset }
+            | StripError::InvalidState { offset, .. } => *offset,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CommentMatch {
@@ -82,7 +116,11 @@ pub fn remove_matches(
         if m.from <= input.len() && m.to <= input.len() && m.from <= m.to {
             input.drain(m.from..m.to);
         } else {
-            return Err("Invalid match range encountered during removal");
+            return Err(StripError::OutOfBounds {
+                from: m.from,
+                to: m.to,
+                len: input.len(),
+            });
         }
     }
     Ok(input)
@@ -92,8 +130,11 @@ fn check_matches_bounds(input: &str, matches: &[CommentMatch]) -> Result<(), Str
     let len = input.len();
     for m in matches {
         if m.from > len || m.to > len || m.from > m.to {
-            eprintln!("Invalid bounds: from={}, to={}, len={}", m.from, m.to, len);
-            return Err("Match indices out of bounds or invalid range (from > to)");
+            return Err(StripError::OutOfBounds {
+                from: m.from,
+                to: m.to,
+                len,
+            });
         }
     }
     Ok(())
@@ -103,8 +144,7 @@ fn check_sorted_matches_overlap(matches: &[CommentMatch]) -> Result<(), StripErr
     let mut last_to = 0;
     for m in matches {
         if m.from < last_to {
-            eprintln!("Overlap: from={}, last_to={}", m.from, last_to);
-            return Err("Matches are overlapping");
+            return Err(StripError::Overlap { offset: m.from });
         }
         last_to = m.to;
     }
@@ -131,13 +131,14 @@ fn do_action(
 ) -> Result<(CommentTrackState, Vec<CommentMatch>), StripError> {
     match action {
         ParseAction::Nothing => {
-            if let Some(start) = state.potential_line_start_pos {
-                if !state.is_line_comment && state.nest_level == 0 {
-                    state.is_line_comment = true;
-                    state.start_pos = start;
-                    state.potential_line_start_pos = None;
-                    state.potential_block_start_pos = None;
-                }
+            if let Some(start) = state.potential_line_start_pos
+                && !state.is_line_comment
+                && state.nest_level == 0
+            {
+                state.is_line_comment = true;
+                state.start_pos = start;
+                state.potential_line_start_pos = None;
+                state.potential_block_start_pos = None;
             }
         }
         ParseAction::PotentialCommentHash => {
@@ -192,9 +192,7 @@ fn do_action(
             }
         }
         ParseAction::MaybeBlockStart => {
-            if state.nest_level == 0 && !state.is_line_comment {
-                state.potential_block_start_pos = Some(position);
-            } else if state.nest_level > 0 {
+            if state.nest_level > 0 || !state.is_line_comment {
                 state.potential_block_start_pos = Some(position);
             }
         }
@@ -10,8 +10,14 @@ enum RustParseState {
     MultiLineCommentSawStar,
     StringDoubleQuotes,
     StringDoubleQuotesEscaped,
+    CharSingleQuotesOpened,
+    CharOrLifetime,
     CharSingleQuotes,
     CharSingleQuotesEscaped,
+    MaybeRawString,
+    RawStringHashes(u8),
+    RawString(u8),
+    RawStringClosing(u8, u8),
     End,
 }
 impl Start for RustParseState {
@@ -72,6 +78,21 @@ fn rust_state_transition(
             RustParseState::StringDoubleQuotesEscaped => {
                 (RustParseState::StringDoubleQuotes, RustParseAction::Nothing)
             }
+            RustParseState::CharSingleQuotesOpened => match c {
+                '\\' => (
+                    RustParseState::CharSingleQuotesEscaped,
+                    RustParseAction::Nothing,
+                ),
+                c if c.is_alphanumeric() || c == '_' => {
+                    (RustParseState::CharOrLifetime, RustParseAction::Nothing)
+                }
+                _ => (RustParseState::CharSingleQuotes, RustParseAction::Nothing),
+            },
+            // `'a'` is a char literal, `'a` followed by anything else a lifetime or label.
+            RustParseState::CharOrLifetime => match c {
+                '\'' => (RustParseState::Normal, RustParseAction::Nothing),
+                _ => rust_state_transition(RustParseState::Normal, Some(c)),
+            },
             RustParseState::CharSingleQuotes => match c {
                 '\'' => (RustParseState::Normal, RustParseAction::Nothing),
                 '\\' => (
@@ -84,13 +105,51 @@ fn rust_state_transition(
                 (RustParseState::CharSingleQuotes, RustParseAction::Nothing)
             }
 
+            RustParseState::MaybeRawString => match c {
+                '#' => (RustParseState::RawStringHashes(1), RustParseAction::Nothing),
+                '"' => (RustParseState::RawString(0), RustParseAction::Nothing),
+                _ => rust_state_transition(RustParseState::Normal, Some(c)),
+            },
+            RustParseState::RawStringHashes(hashes) => match c {
+                '#' => (
+                    RustParseState::RawStringHashes(hashes.saturating_add(1)),
+                    RustParseAction::Nothing,
+                ),
+                '"' => (RustParseState::RawString(hashes), RustParseAction::Nothing),
+                _ => rust_state_transition(RustParseState::Normal, Some(c)),
+            },
+            RustParseState::RawString(hashes) => match c {
+                '"' if hashes == 0 => (RustParseState::Normal, RustParseAction::Nothing),
+                '"' => (
+                    RustParseState::RawStringClosing(hashes, 0),
+                    RustParseAction::Nothing,
+                ),
+                _ => (RustParseState::RawString(hashes), RustParseAction::Nothing),
+            },
+            RustParseState::RawStringClosing(hashes, seen) => match c {
+                '#' if seen + 1 == hashes => (RustParseState::Normal, RustParseAction::Nothing),
+                '#' => (
+                    RustParseState::RawStringClosing(hashes, seen + 1),
+                    RustParseAction::Nothing,
+                ),
+                '"' => (
+                    RustParseState::RawStringClosing(hashes, 0),
+                    RustParseAction::Nothing,
+                ),
+                _ => (RustParseState::RawString(hashes), RustParseAction::Nothing),
+            },
+
             RustParseState::Start | RustParseState::Normal => match c {
                 '/' => (
                     RustParseState::FirstSlash,
                     RustParseAction::CommentMightStart,
                 ),
                 '"' => (RustParseState::StringDoubleQuotes, RustParseAction::Nothing),
-                '\'' => (RustParseState::CharSingleQuotes, RustParseAction::Nothing),
+                '\'' => (
+                    RustParseState::CharSingleQuotesOpened,
+                    RustParseAction::Nothing,
+                ),
+                'r' => (RustParseState::MaybeRawString, RustParseAction::Nothing),
                 _ => (RustParseState::Normal, RustParseAction::Nothing),
             },
             RustParseState::FirstSlash => match c {
@@ -147,9 +206,7 @@ fn rust_do_action(
     match action {
         RustParseAction::Nothing => {}
         RustParseAction::CommentMightStart => {
-            if state.nest_level == 0 && !state.in_line_comment {
-                state.potential_start_pos = Some(position);
-            } else if state.nest_level > 0 {
+            if state.nest_level > 0 || !state.in_line_comment {
                 state.potential_start_pos = Some(position);
             }
         }
@@ -196,16 +253,14 @@ fn rust_do_action(
                     to: position,
                 });
                 state = RustCommentTrackState::start();
-            } else if state.nest_level > 0 {
-                if action == RustParseAction::DecrementNest {
-                    state.nest_level -= 1;
-                    if state.nest_level == 0 {
-                        matches.push(CommentMatch {
-                            from: state.start_pos,
-                            to: position + 1,
-                        });
-                        state = RustCommentTrackState::start();
-                    }
+            } else if state.nest_level > 0 && action == RustParseAction::DecrementNest {
+                state.nest_level -= 1;
+                if state.nest_level == 0 {
+                    matches.push(CommentMatch {
+                        from: state.start_pos,
+                        to: position + 1,
+                    });
+                    state = RustCommentTrackState::start();
                 }
             }
             state.potential_start_pos = None;
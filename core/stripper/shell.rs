@@ -4,6 +4,12 @@ use super::common::{CommentMatch, End, Start, StripError, find_comments_impl};
 pub(crate) enum ShParseState {
     StartOfLine,
     Normal,
+    Word,
+    Escaped,
+    Dollar,
+    DollarParen,
+    Parameter(u8),
+    Arithmetic(u8),
     PotentialShebang,
     SawHash,
     StringDbl,
@@ -52,11 +58,8 @@ pub(crate) fn sh_state_transition(
                     ShParseState::PotentialShebang,
                     ParseAction::PotentialShebang,
                 ),
-                '"' => (ShParseState::StringDbl, ParseAction::Nothing),
-                '\'' => (ShParseState::StringSgl, ParseAction::Nothing),
-                ' ' | '\t' => (ShParseState::StartOfLine, ParseAction::Nothing),
-                '\n' => (ShParseState::StartOfLine, ParseAction::Nothing),
-                _ => (ShParseState::Normal, ParseAction::Nothing),
+                ' ' | '\t' | '\n' => (ShParseState::StartOfLine, ParseAction::Nothing),
+                _ => sh_state_transition(ShParseState::Normal, Some(c)),
             },
             ShParseState::PotentialShebang => match c {
                 '!' => (ShParseState::Normal, ParseAction::ShebangConfirmed),
@@ -67,21 +70,60 @@ pub(crate) fn sh_state_transition(
                 '#' => (ShParseState::SawHash, ParseAction::CommentStart),
                 '"' => (ShParseState::StringDbl, ParseAction::Nothing),
                 '\'' => (ShParseState::StringSgl, ParseAction::Nothing),
+                '\\' => (ShParseState::Escaped, ParseAction::Nothing),
+                '$' => (ShParseState::Dollar, ParseAction::Nothing),
                 '\n' => (ShParseState::StartOfLine, ParseAction::Nothing),
-                _ => (ShParseState::Normal, ParseAction::Nothing),
+                ' ' | '\t' | ';' | '|' | '&' | '(' | ')' => {
+                    (ShParseState::Normal, ParseAction::Nothing)
+                }
+                _ => (ShParseState::Word, ParseAction::Nothing),
+            },
+            // `#` only starts a comment at the beginning of a word.
+            ShParseState::Word => match c {
+                '#' => (ShParseState::Word, ParseAction::Nothing),
+                _ => sh_state_transition(ShParseState::Normal, Some(c)),
+            },
+            ShParseState::Escaped => (ShParseState::Word, ParseAction::Nothing),
+            ShParseState::Dollar => match c {
+                '{' => (ShParseState::Parameter(1), ParseAction::Nothing),
+                '(' => (ShParseState::DollarParen, ParseAction::Nothing),
+                '#' => (ShParseState::Word, ParseAction::Nothing),
+                _ => sh_state_transition(ShParseState::Word, Some(c)),
+            },
+            ShParseState::DollarParen => match c {
+                '(' => (ShParseState::Arithmetic(2), ParseAction::Nothing),
+                _ => sh_state_transition(ShParseState::Normal, Some(c)),
+            },
+            ShParseState::Parameter(depth) => match c {
+                '{' => (
+                    ShParseState::Parameter(depth.saturating_add(1)),
+                    ParseAction::Nothing,
+                ),
+                '}' if depth == 1 => (ShParseState::Word, ParseAction::Nothing),
+                '}' => (ShParseState::Parameter(depth - 1), ParseAction::Nothing),
+                _ => (ShParseState::Parameter(depth), ParseAction::Nothing),
+            },
+            ShParseState::Arithmetic(depth) => match c {
+                '(' => (
+                    ShParseState::Arithmetic(depth.saturating_add(1)),
+                    ParseAction::Nothing,
+                ),
+                ')' if depth == 1 => (ShParseState::Word, ParseAction::Nothing),
+                ')' => (ShParseState::Arithmetic(depth - 1), ParseAction::Nothing),
+                _ => (ShParseState::Arithmetic(depth), ParseAction::Nothing),
             },
             ShParseState::SawHash => match c {
                 '\n' => (ShParseState::StartOfLine, ParseAction::CommentEnd),
                 _ => (ShParseState::SawHash, ParseAction::Nothing),
             },
             ShParseState::StringDbl => match c {
-                '"' => (ShParseState::Normal, ParseAction::Nothing),
+                '"' => (ShParseState::Word, ParseAction::Nothing),
                 '\\' => (ShParseState::StringDblEsc, ParseAction::Nothing),
                 _ => (ShParseState::StringDbl, ParseAction::Nothing),
             },
             ShParseState::StringDblEsc => (ShParseState::StringDbl, ParseAction::Nothing),
             ShParseState::StringSgl => match c {
-                '\'' => (ShParseState::Normal, ParseAction::Nothing),
+                '\'' => (ShParseState::Word, ParseAction::Nothing),
                 _ => (ShParseState::StringSgl, ParseAction::Nothing),
             },
             ShParseState::End => (ShParseState::End, ParseAction::Nothing),
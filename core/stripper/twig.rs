@@ -32,6 +32,7 @@ enum ParseAction {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 enum CommentTrackState {
     NotInComment,
     MaybeComment(usize),
@@ -138,7 +138,10 @@ fn do_action(
             if let CommentTrackState::MaybeComment(from) = comment_state {
                 comment_state = CommentTrackState::InComment(from);
             } else {
-                return Err("XML Stripper Error: Invalid state transition to CommentStart");
+                return Err(StripError::InvalidState {
+                    offset: position,
+                    message: "XML comment start without a pending '<!'",
+                });
             }
         }
         ParseAction::CommentEnd => {